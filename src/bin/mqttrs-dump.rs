@@ -0,0 +1,659 @@
+//! Print the MQTT packets found in a capture file.
+//!
+//! The capture is either a pcap or pcapng file, in which the TCP flows to or from the MQTT port are
+//! reassembled, or the raw bytes of one direction of a TCP stream.
+
+use mqttrs::{decode_slice, peek_header};
+use std::{
+    collections::{BTreeMap, HashMap},
+    env, fs,
+    io::{self, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    process,
+};
+
+const USAGE: &str = "Usage: mqttrs-dump [--raw] [--port PORT] FILE
+
+Print the MQTT packets found in FILE. FILE is either a pcap or pcapng capture, in which the TCP
+flows to or from PORT (default 1883) are reassembled, or the raw bytes of one direction of a TCP
+stream.
+
+Options:
+    --raw         Treat FILE as raw stream bytes, even if it looks like a pcap file
+    --port PORT   MQTT server port to look for in pcap files";
+
+fn main() {
+    let mut raw = false;
+    let mut port = 1883;
+    let mut path = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--raw" => raw = true,
+            "--port" => match args.next().and_then(|p| p.parse().ok()) {
+                Some(p) => port = p,
+                None => usage(),
+            },
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ if path.is_none() && !arg.starts_with('-') => path = Some(arg),
+            _ => usage(),
+        }
+    }
+    let path = path.unwrap_or_else(|| usage());
+    let data = fs::read(&path).unwrap_or_else(|e| {
+        eprintln!("{}: {}", path, e);
+        process::exit(1)
+    });
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let res = if !raw && Pcap::new(&data).is_ok() {
+        dump_pcap(&data, port, &mut out)
+    } else {
+        let mut stream = Stream::default();
+        stream.extend(&data);
+        stream.dump("", &mut out).map_err(|e| e.to_string())
+    };
+    if let Err(e) = res {
+        eprintln!("{}: {}", path, e);
+        process::exit(1);
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2)
+}
+
+/// Decode and print all the packets of a pcap or pcapng capture.
+fn dump_pcap(data: &[u8], port: u16, out: &mut impl Write) -> Result<(), String> {
+    let mut streams: HashMap<(SocketAddr, SocketAddr), Stream> = HashMap::new();
+    for record in Pcap::new(data)? {
+        let (ts, link_type, frame) = record?;
+        let seg = match parse_frame(link_type, frame) {
+            Some(seg) if seg.src.port() == port || seg.dst.port() == port => seg,
+            _ => continue,
+        };
+        let stream = streams.entry((seg.src, seg.dst)).or_default();
+        stream.push(seg.seq, seg.syn, seg.payload);
+        let prefix = format!("{} {} -> {} ", ts, seg.src, seg.dst);
+        stream.dump(&prefix, out).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// One direction of a TCP flow, reassembled.
+#[derive(Default)]
+struct Stream {
+    /// Bytes received in order and not yet decoded.
+    buf: Vec<u8>,
+    /// Stream offset of `buf[0]`.
+    offset: usize,
+    /// Sequence number of the next expected byte.
+    next_seq: Option<u32>,
+    /// Out of order segments.
+    pending: BTreeMap<u32, Vec<u8>>,
+    /// Set after a decoding error: we can't find packet boundaries anymore.
+    failed: bool,
+}
+
+impl Stream {
+    fn extend(&mut self, data: &[u8]) {
+        if !self.failed {
+            self.buf.extend_from_slice(data);
+        }
+    }
+
+    /// Add a TCP segment, reordering it if needed.
+    fn push(&mut self, seq: u32, syn: bool, payload: &[u8]) {
+        let (seq, payload) = if syn {
+            (seq.wrapping_add(1), &[][..])
+        } else {
+            (seq, payload)
+        };
+        let next = *self.next_seq.get_or_insert(seq);
+        let ahead = seq.wrapping_sub(next) as i32;
+        if ahead > 0 {
+            self.pending.insert(seq, payload.to_vec());
+            return;
+        }
+        // Skip retransmitted bytes
+        let skip = (-ahead) as usize;
+        if skip < payload.len() {
+            self.extend(&payload[skip..]);
+            self.next_seq = Some(next.wrapping_add((payload.len() - skip) as u32));
+        }
+        // Pending segments that are now in order
+        while let Some(&seq) = self.pending.keys().next() {
+            if seq.wrapping_sub(self.next_seq.unwrap()) as i32 > 0 {
+                break;
+            }
+            let payload = self.pending.remove(&seq).unwrap();
+            self.push(seq, false, &payload);
+        }
+    }
+
+    /// Print all the complete packets in the buffer, prefixing each line with `prefix`.
+    fn dump(&mut self, prefix: &str, out: &mut impl Write) -> io::Result<()> {
+        let mut pos = 0;
+        while !self.failed {
+            let buf = &self.buf[pos..];
+            let res = peek_header(buf).and_then(|header| match header {
+                Some((_, header_len, remaining_len)) => {
+                    Ok(decode_slice(buf)?.map(|pkt| (pkt, header_len + remaining_len)))
+                }
+                None => Ok(None),
+            });
+            let len = match res {
+                Ok(Some((pkt, len))) => {
+                    writeln!(out, "{}{}", prefix, pkt)?;
+                    len
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    writeln!(
+                        out,
+                        "{}error at offset {}: {}",
+                        prefix,
+                        self.offset + pos,
+                        e
+                    )?;
+                    self.failed = true;
+                    self.buf.clear();
+                    return Ok(());
+                }
+            };
+            pos += len;
+        }
+        self.buf.drain(..pos);
+        self.offset += pos;
+        Ok(())
+    }
+}
+
+/// Iterator over the records of a pcap or pcapng file.
+struct Pcap<'a> {
+    data: &'a [u8],
+    pos: usize,
+    big_endian: bool,
+    /// Whether this is a pcapng file rather than a classic pcap one.
+    ng: bool,
+    /// Link type and timestamp units per second of each interface. Classic pcap files have a
+    /// single one, pcapng files describe theirs in each section.
+    interfaces: Vec<(u32, u64)>,
+}
+
+/// pcapng block types.
+const SECTION_HEADER: u32 = 0x0A0D_0D0A;
+const INTERFACE_DESCRIPTION: u32 = 1;
+const ENHANCED_PACKET: u32 = 6;
+
+impl<'a> Pcap<'a> {
+    fn new(data: &'a [u8]) -> Result<Self, String> {
+        let (big_endian, units) = match data.get(..4) {
+            Some([0xd4, 0xc3, 0xb2, 0xa1]) => (false, 1_000_000),
+            Some([0xa1, 0xb2, 0xc3, 0xd4]) => (true, 1_000_000),
+            Some([0x4d, 0x3c, 0xb2, 0xa1]) => (false, 1_000_000_000),
+            Some([0xa1, 0xb2, 0x3c, 0x4d]) => (true, 1_000_000_000),
+            // The byte order is read from each section header
+            Some([0x0a, 0x0d, 0x0d, 0x0a]) => {
+                return Ok(Pcap {
+                    data,
+                    pos: 0,
+                    big_endian: false,
+                    ng: true,
+                    interfaces: Vec::new(),
+                })
+            }
+            _ => return Err("not a pcap file".into()),
+        };
+        if data.len() < 24 {
+            return Err("truncated pcap header".into());
+        }
+        let mut pcap = Pcap {
+            data,
+            pos: 24,
+            big_endian,
+            ng: false,
+            interfaces: Vec::new(),
+        };
+        pcap.interfaces.push((pcap.u32(20), units));
+        Ok(pcap)
+    }
+
+    fn u16(&self, pos: usize) -> u16 {
+        let b = [self.data[pos], self.data[pos + 1]];
+        if self.big_endian {
+            u16::from_be_bytes(b)
+        } else {
+            u16::from_le_bytes(b)
+        }
+    }
+
+    fn u32(&self, pos: usize) -> u32 {
+        let b = [
+            self.data[pos],
+            self.data[pos + 1],
+            self.data[pos + 2],
+            self.data[pos + 3],
+        ];
+        if self.big_endian {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        }
+    }
+
+    /// Read the next classic pcap record.
+    fn next_pcap(&mut self) -> Result<Option<Record<'a>>, String> {
+        if self.pos == self.data.len() {
+            return Ok(None);
+        }
+        if self.pos + 16 > self.data.len() {
+            return Err("truncated pcap record header".into());
+        }
+        let secs = self.u32(self.pos);
+        let frac = self.u32(self.pos + 4);
+        let len = self.u32(self.pos + 8) as usize;
+        let start = self.pos + 16;
+        if start + len > self.data.len() {
+            return Err("truncated pcap record".into());
+        }
+        self.pos = start + len;
+        let (link_type, units) = self.interfaces[0];
+        let ts = u64::from(secs) * units + u64::from(frac);
+        Ok(Some((
+            Timestamp::new(ts, units),
+            link_type,
+            &self.data[start..start + len],
+        )))
+    }
+
+    /// Read pcapng blocks up to the next packet.
+    fn next_pcapng(&mut self) -> Result<Option<Record<'a>>, String> {
+        while self.pos < self.data.len() {
+            let start = self.pos;
+            if start + 12 > self.data.len() {
+                return Err("truncated pcapng block header".into());
+            }
+            // The section header block type reads the same in both byte orders
+            let block_type = self.u32(start);
+            if block_type == SECTION_HEADER {
+                self.big_endian = match self.data[start + 8..start + 12] {
+                    [0x1a, 0x2b, 0x3c, 0x4d] => true,
+                    [0x4d, 0x3c, 0x2b, 0x1a] => false,
+                    _ => return Err("invalid pcapng byte-order magic".into()),
+                };
+                self.interfaces.clear();
+            }
+            let len = self.u32(start + 4) as usize;
+            if len < 12 || len % 4 != 0 {
+                return Err(format!("invalid pcapng block length {}", len));
+            }
+            if start + len > self.data.len() {
+                return Err("truncated pcapng block".into());
+            }
+            self.pos = start + len;
+            let body = start + 8..start + len - 4;
+            match block_type {
+                INTERFACE_DESCRIPTION => {
+                    if body.len() < 8 {
+                        return Err("truncated pcapng interface description".into());
+                    }
+                    let link_type = u32::from(self.u16(body.start));
+                    let units = self.tsresol(body.start + 8..body.end)?;
+                    self.interfaces.push((link_type, units));
+                }
+                ENHANCED_PACKET => {
+                    if body.len() < 20 {
+                        return Err("truncated pcapng packet header".into());
+                    }
+                    let interface = self.u32(body.start) as usize;
+                    let (link_type, units) = *self
+                        .interfaces
+                        .get(interface)
+                        .ok_or_else(|| format!("unknown pcapng interface {}", interface))?;
+                    let ts = u64::from(self.u32(body.start + 4)) << 32
+                        | u64::from(self.u32(body.start + 8));
+                    let captured = self.u32(body.start + 12) as usize;
+                    let frame = self.data[body.start + 20..body.end]
+                        .get(..captured)
+                        .ok_or("truncated pcapng packet")?;
+                    return Ok(Some((Timestamp::new(ts, units), link_type, frame)));
+                }
+                // Statistics, name resolution, etc
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+
+    /// Find the timestamp units per second in an interface description's options.
+    fn tsresol(&self, options: std::ops::Range<usize>) -> Result<u64, String> {
+        let mut pos = options.start;
+        while pos + 4 <= options.end {
+            let code = self.u16(pos);
+            let len = self.u16(pos + 2) as usize;
+            // if_tsresol, a negative power of 10, or of 2 if the high bit is set
+            if code == 9 && len == 1 && pos + 4 < options.end {
+                let exp = self.data[pos + 4];
+                let units = if exp & 0x80 == 0 {
+                    10u64.checked_pow(u32::from(exp))
+                } else {
+                    1u64.checked_shl(u32::from(exp & 0x7f))
+                };
+                return units.ok_or_else(|| "invalid pcapng timestamp resolution".into());
+            }
+            // opt_endofopt
+            if code == 0 {
+                break;
+            }
+            pos += 4 + (len + 3) / 4 * 4;
+        }
+        Ok(1_000_000)
+    }
+}
+
+/// Timestamp, link type, and captured bytes of a packet.
+type Record<'a> = (Timestamp, u32, &'a [u8]);
+
+impl<'a> Iterator for Pcap<'a> {
+    type Item = Result<Record<'a>, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let res = if self.ng {
+            self.next_pcapng()
+        } else {
+            self.next_pcap()
+        };
+        match res {
+            Ok(record) => record.map(Ok),
+            Err(e) => {
+                self.pos = self.data.len();
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Seconds and microseconds since the epoch.
+struct Timestamp(u64, u32);
+
+impl Timestamp {
+    /// Convert a timestamp counted in `units` per second.
+    fn new(ts: u64, units: u64) -> Self {
+        let micros = u128::from(ts % units) * 1_000_000 / u128::from(units);
+        Timestamp(ts / units, micros as u32)
+    }
+}
+
+impl std::fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}.{:06}", self.0, self.1)
+    }
+}
+
+/// The interesting parts of a TCP segment.
+struct Segment<'a> {
+    src: SocketAddr,
+    dst: SocketAddr,
+    seq: u32,
+    syn: bool,
+    payload: &'a [u8],
+}
+
+/// Parse a captured frame down to its TCP payload, returns None for anything else.
+fn parse_frame(link_type: u32, frame: &[u8]) -> Option<Segment<'_>> {
+    let ip = match link_type {
+        // Ethernet, possibly with VLAN tags
+        1 => {
+            let mut pos = 12;
+            while frame.get(pos..pos + 2)? == [0x81, 0x00] {
+                pos += 4;
+            }
+            frame.get(pos + 2..)?
+        }
+        // BSD loopback
+        0 => frame.get(4..)?,
+        // Linux cooked capture
+        113 => frame.get(16..)?,
+        // Raw IP
+        101 | 228 | 229 => frame,
+        _ => return None,
+    };
+    let (src, dst, tcp) = match ip.first()? >> 4 {
+        4 => {
+            let header_len = (ip[0] & 0xf) as usize * 4;
+            let total_len = u16::from_be_bytes([*ip.get(2)?, *ip.get(3)?]) as usize;
+            if *ip.get(9)? != 6 || header_len < 20 || total_len < header_len {
+                return None;
+            }
+            let mut src = [0u8; 4];
+            src.copy_from_slice(ip.get(12..16)?);
+            let mut dst = [0u8; 4];
+            dst.copy_from_slice(ip.get(16..20)?);
+            (
+                IpAddr::V4(Ipv4Addr::from(src)),
+                IpAddr::V4(Ipv4Addr::from(dst)),
+                ip.get(header_len..total_len)?,
+            )
+        }
+        6 => {
+            let payload_len = u16::from_be_bytes([*ip.get(4)?, *ip.get(5)?]) as usize;
+            if *ip.get(6)? != 6 {
+                return None;
+            }
+            let mut src = [0u8; 16];
+            src.copy_from_slice(ip.get(8..24)?);
+            let mut dst = [0u8; 16];
+            dst.copy_from_slice(ip.get(24..40)?);
+            let tcp = ip.get(40..40 + payload_len)?;
+            (
+                IpAddr::V6(Ipv6Addr::from(src)),
+                IpAddr::V6(Ipv6Addr::from(dst)),
+                tcp,
+            )
+        }
+        _ => return None,
+    };
+    let header_len = (*tcp.get(12)? >> 4) as usize * 4;
+    if header_len < 20 {
+        return None;
+    }
+    Some(Segment {
+        src: SocketAddr::new(src, u16::from_be_bytes([tcp[0], tcp[1]])),
+        dst: SocketAddr::new(dst, u16::from_be_bytes([tcp[2], tcp[3]])),
+        seq: u32::from_be_bytes([tcp[4], tcp[5], tcp[6], tcp[7]]),
+        syn: tcp.get(13)? & 0x02 != 0,
+        payload: tcp.get(header_len..)?,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mqttrs::{Direction, Packet};
+    use std::time::Duration;
+
+    /// Build an Ethernet/IPv4/TCP frame.
+    fn frame(src_port: u16, dst_port: u16, seq: u32, syn: bool, payload: &[u8]) -> Vec<u8> {
+        let mut f = vec![0u8; 12];
+        f.extend_from_slice(&[0x08, 0x00]);
+        let total_len = (20 + 20 + payload.len()) as u16;
+        f.extend_from_slice(&[0x45, 0, 0, 0, 0, 0, 0, 0, 64, 6, 0, 0]);
+        f[16..18].copy_from_slice(&total_len.to_be_bytes());
+        f.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2]);
+        f.extend_from_slice(&src_port.to_be_bytes());
+        f.extend_from_slice(&dst_port.to_be_bytes());
+        f.extend_from_slice(&seq.to_be_bytes());
+        f.extend_from_slice(&[
+            0,
+            0,
+            0,
+            0,
+            0x50,
+            if syn { 0x02 } else { 0x18 },
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        ]);
+        f.extend_from_slice(payload);
+        f
+    }
+
+    fn pcap(frames: &[Vec<u8>]) -> Vec<u8> {
+        let mut p = vec![0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0];
+        p.extend_from_slice(&[0; 8]);
+        p.extend_from_slice(&65535u32.to_le_bytes());
+        p.extend_from_slice(&1u32.to_le_bytes());
+        for (i, f) in frames.iter().enumerate() {
+            p.extend_from_slice(&(1000 + i as u32).to_le_bytes());
+            p.extend_from_slice(&42u32.to_le_bytes());
+            p.extend_from_slice(&(f.len() as u32).to_le_bytes());
+            p.extend_from_slice(&(f.len() as u32).to_le_bytes());
+            p.extend_from_slice(f);
+        }
+        p
+    }
+
+    /// Build a little-endian pcapng block.
+    fn block(block_type: u32, body: &[u8]) -> Vec<u8> {
+        let len = (12 + body.len()) as u32;
+        let mut b = block_type.to_le_bytes().to_vec();
+        b.extend_from_slice(&len.to_le_bytes());
+        b.extend_from_slice(body);
+        b.extend_from_slice(&len.to_le_bytes());
+        b
+    }
+
+    #[test]
+    fn reassembly() {
+        let publish = [0b00110000, 7, 0, 1, b'a', b'h', b'e', b'l', b'o'];
+        let data = pcap(&[
+            frame(5000, 1883, 99, true, &[]),
+            // Out of order
+            frame(5000, 1883, 104, false, &publish[4..]),
+            frame(5000, 1883, 100, false, &publish[..4]),
+            // Retransmission, followed by a pingreq
+            frame(
+                5000,
+                1883,
+                102,
+                false,
+                &[0, 1, b'a', b'h', b'e', b'l', b'o', 0b11000000, 0],
+            ),
+            frame(1883, 5000, 7, false, &[0b11010000, 0]),
+            // Other port
+            frame(5000, 80, 1, false, &[0b11010000, 0]),
+        ]);
+        let mut out = Vec::new();
+        dump_pcap(&data, 1883, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "1002.000042 10.0.0.1:5000 -> 10.0.0.2:1883 PUBLISH q0 topic=\"a\" 4B\n\
+             1003.000042 10.0.0.1:5000 -> 10.0.0.2:1883 PINGREQ\n\
+             1004.000042 10.0.0.1:1883 -> 10.0.0.2:5000 PINGRESP\n"
+        );
+    }
+
+    #[test]
+    fn pcapng() {
+        let mut writer = mqttrs::PcapWriter::new(Vec::new()).unwrap();
+        let ts = Duration::new(1_600_000_000, 500_000_000);
+        writer
+            .write_packet(Direction::Outbound, ts, &Packet::Pingreq)
+            .unwrap();
+        writer
+            .write_packet(Direction::Inbound, ts, &Packet::Pingresp)
+            .unwrap();
+        let data = writer.into_inner().unwrap();
+        let mut out = Vec::new();
+        dump_pcap(&data, 1883, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "1600000000.500000 127.0.0.1:49152 -> 127.0.0.2:1883 PINGREQ\n\
+             1600000000.500000 127.0.0.2:1883 -> 127.0.0.1:49152 PINGRESP\n"
+        );
+
+        // Second section, with an Ethernet interface in nanoseconds
+        let mut data = data;
+        let mut shb = vec![0x4d, 0x3c, 0x2b, 0x1a, 1, 0, 0, 0];
+        shb.extend_from_slice(&[0xff; 8]);
+        data.extend(block(SECTION_HEADER, &shb));
+        let mut idb = vec![1, 0, 0, 0, 0, 0, 0, 0];
+        // Padded if_tsresol option, then opt_endofopt
+        idb.extend_from_slice(&[9, 0, 1, 0, 9, 0, 0, 0, 0, 0, 0, 0]);
+        data.extend(block(INTERFACE_DESCRIPTION, &idb));
+        let f = frame(5000, 1883, 1, false, &[0b11100000, 0]);
+        let ts = 1_000_000_123_456u64;
+        let mut epb = 0u32.to_le_bytes().to_vec();
+        epb.extend_from_slice(&((ts >> 32) as u32).to_le_bytes());
+        epb.extend_from_slice(&(ts as u32).to_le_bytes());
+        epb.extend_from_slice(&(f.len() as u32).to_le_bytes());
+        epb.extend_from_slice(&(f.len() as u32).to_le_bytes());
+        epb.extend_from_slice(&f);
+        epb.resize((epb.len() + 3) / 4 * 4, 0);
+        data.extend(block(ENHANCED_PACKET, &epb));
+        let mut out = Vec::new();
+        dump_pcap(&data, 1883, &mut out).unwrap();
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .ends_with("PINGRESP\n1000.000123 10.0.0.1:5000 -> 10.0.0.2:1883 DISCONNECT\n"));
+
+        // Packets of undescribed interfaces
+        data.extend(block(ENHANCED_PACKET, &[1; 20]));
+        let err = dump_pcap(&data, 1883, &mut Vec::new()).unwrap_err();
+        assert_eq!(err, "unknown pcapng interface 16843009");
+    }
+
+    #[test]
+    fn truncated_frames() {
+        let full = frame(5000, 1883, 1, false, &[0b11010000, 0]);
+        for len in 0..full.len() {
+            let mut ip = full[14..len.max(14)].to_vec();
+            // Claim a short total length, so that only the truncation can stop parsing
+            if ip.len() >= 4 {
+                ip[2..4].copy_from_slice(&20u16.to_be_bytes());
+            }
+            assert!(
+                parse_frame(101, &ip).is_none(),
+                "raw ip, {} bytes",
+                ip.len()
+            );
+            assert!(
+                parse_frame(1, &full[..len]).is_none(),
+                "ethernet, {} bytes",
+                len
+            );
+        }
+        // Header lengths shorter than the fixed headers
+        let mut bad = full[14..].to_vec();
+        bad[0] = 0x41;
+        assert!(parse_frame(101, &bad).is_none());
+        let mut bad = full[14..].to_vec();
+        bad[32] = 0x10;
+        assert!(parse_frame(101, &bad).is_none());
+        assert!(parse_frame(101, &full[14..]).is_some());
+    }
+
+    #[test]
+    fn raw_error() {
+        let mut stream = Stream::default();
+        stream.extend(&[0b11000000, 0, 0b11000001, 0, 0b11000000, 0]);
+        let mut out = Vec::new();
+        stream.dump("", &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap().lines().collect::<Vec<_>>(),
+            [
+                "PINGREQ",
+                "error at offset 2: invalid fixed header flags 0b0001 for PINGREQ"
+            ]
+        );
+    }
+}
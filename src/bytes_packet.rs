@@ -0,0 +1,422 @@
+use crate::{
+    decoder::{check_packet_size, decode_with_len},
+    *,
+};
+use bytes::{Bytes, BytesMut};
+use core::str::from_utf8;
+
+/// Decode the packet at the start of `buf` as a [BytesPacket], whose strings and binary fields are
+/// reference-counted slices of `buf` instead of copies.
+///
+/// Returns the packet with its encoded length, so that `buf` can be advanced to the next packet.
+/// Like [decode_slice()], returns `Ok(None)` if `buf` doesn't contain a whole packet yet.
+///
+/// ```
+/// # use mqttrs::*;
+/// # use bytes::{Buf, Bytes};
+/// // A publish followed by a pingreq
+/// let data = [0b00110000, 7, 0, 1, b'a', b'h', b'e', b'l', b'l', 0b11000000, 0];
+/// let mut buf = Bytes::copy_from_slice(&data);
+/// let (packet, len) = decode_bytes(&buf).unwrap().unwrap();
+/// let subscribers = match packet {
+///     BytesPacket::Publish(publish) => vec![publish.clone(), publish],
+///     other => panic!("unexpected {:?}", other),
+/// };
+/// assert_eq!(subscribers[1].payload, &b"hell"[..]);
+/// assert_eq!(subscribers[1].payload.as_ptr(), buf[5..].as_ptr());
+///
+/// buf.advance(len);
+/// assert_eq!(Ok(Some((BytesPacket::Pingreq, 2))), decode_bytes(&buf));
+/// ```
+///
+/// [BytesPacket]: enum.BytesPacket.html
+/// [decode_slice()]: fn.decode_slice.html
+pub fn decode_bytes(buf: &Bytes) -> Result<Option<(BytesPacket, usize)>, Error> {
+    decode_bytes_with_limit(buf, usize::MAX)
+}
+
+/// Like [decode_bytes()], but fails with `Error::PacketTooLarge` as soon as the fixed header
+/// announces a packet bigger than `max_packet_size` bytes, like [decode_slice_with_limit()].
+///
+/// [decode_bytes()]: fn.decode_bytes.html
+/// [decode_slice_with_limit()]: fn.decode_slice_with_limit.html
+pub fn decode_bytes_with_limit(
+    buf: &Bytes,
+    max_packet_size: usize,
+) -> Result<Option<(BytesPacket, usize)>, Error> {
+    Ok(decode_with_len(buf, max_packet_size)?
+        .map(|(packet, len)| (BytesPacket::new(buf, packet), len)))
+}
+
+/// Like [decode_bytes()], for a receive buffer: once it holds a whole packet, the packet's bytes
+/// are split off the front of `buf` and frozen, so that the next packet can be received.
+///
+/// ```
+/// # use mqttrs::*;
+/// # use bytes::BytesMut;
+/// let mut buf = BytesMut::from(&[0b11000000u8, 0, 0b11010000] as &[u8]);
+/// assert_eq!(Ok(Some(BytesPacket::Pingreq)), decode_bytes_mut(&mut buf));
+/// assert_eq!(Ok(None), decode_bytes_mut(&mut buf));
+/// assert_eq!(&buf[..], &[0b11010000]);
+/// ```
+///
+/// [decode_bytes()]: fn.decode_bytes.html
+pub fn decode_bytes_mut(buf: &mut BytesMut) -> Result<Option<BytesPacket>, Error> {
+    decode_bytes_mut_with_limit(buf, usize::MAX)
+}
+
+/// Like [decode_bytes_mut()], but fails with `Error::PacketTooLarge` as soon as the fixed header
+/// announces a packet bigger than `max_packet_size` bytes, instead of waiting for `buf` to hold
+/// all of it.
+///
+/// ```
+/// # use mqttrs::*;
+/// # use bytes::BytesMut;
+/// // Publish header announcing 1000 bytes, only the first ones received so far.
+/// let mut buf = BytesMut::from(&[0b00110000u8, 0xe8, 0x07, 0, 1, b'a'] as &[u8]);
+/// assert_eq!(Ok(None), decode_bytes_mut(&mut buf));
+/// assert_eq!(
+///     Err(Error::PacketTooLarge { size: 1003, max: 256 }),
+///     decode_bytes_mut_with_limit(&mut buf, 256)
+/// );
+/// ```
+///
+/// [decode_bytes_mut()]: fn.decode_bytes_mut.html
+pub fn decode_bytes_mut_with_limit(
+    buf: &mut BytesMut,
+    max_packet_size: usize,
+) -> Result<Option<BytesPacket>, Error> {
+    match peek_header(buf)? {
+        Some((_, header_len, remaining_len)) => {
+            let len = header_len + remaining_len;
+            check_packet_size(len, max_packet_size)?;
+            if buf.len() < len {
+                return Ok(None);
+            }
+            let frame = buf.split_to(len).freeze();
+            Ok(decode_bytes(&frame)?.map(|(packet, _)| packet))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Like [Packet], but owning its strings and binary fields as [Bytes] slices of the decoded
+/// buffer.
+///
+/// Cloning it only increments a reference count, so that a received publish can be handed to many
+/// tasks without copying its payload. Get a [Packet] view with [as_packet()] to encode it.
+///
+/// [Packet]: enum.Packet.html
+/// [Bytes]: https://docs.rs/bytes/0.5.6/bytes/struct.Bytes.html
+/// [as_packet()]: #method.as_packet
+#[derive(Debug, Clone, PartialEq)]
+pub enum BytesPacket {
+    Connect(BytesConnect),
+    Connack(Connack),
+    Publish(BytesPublish),
+    Puback(Pid),
+    Pubrec(Pid),
+    Pubrel(Pid),
+    Pubcomp(Pid),
+    Subscribe(Subscribe),
+    Suback(Suback),
+    Unsubscribe(Unsubscribe),
+    Unsuback(Pid),
+    Pingreq,
+    Pingresp,
+    Disconnect,
+}
+
+impl BytesPacket {
+    /// Convert a packet decoded from `frame`, which all its fields must point into.
+    fn new(frame: &Bytes, packet: Packet) -> Self {
+        match packet {
+            Packet::Connect(connect) => BytesPacket::Connect(BytesConnect {
+                protocol: connect.protocol,
+                keep_alive: connect.keep_alive,
+                client_id: frame.slice_ref(connect.client_id.as_bytes()),
+                clean_session: connect.clean_session,
+                last_will: connect.last_will.map(|will| BytesLastWill {
+                    topic: frame.slice_ref(will.topic.as_bytes()),
+                    message: frame.slice_ref(will.message),
+                    qos: will.qos,
+                    retain: will.retain,
+                }),
+                username: connect.username.map(|u| frame.slice_ref(u.as_bytes())),
+                password: connect.password.map(|p| frame.slice_ref(p)),
+            }),
+            Packet::Connack(connack) => BytesPacket::Connack(connack),
+            Packet::Publish(publish) => BytesPacket::Publish(BytesPublish {
+                dup: publish.dup,
+                qospid: publish.qospid,
+                retain: publish.retain,
+                topic_name: frame.slice_ref(publish.topic_name.as_bytes()),
+                payload: frame.slice_ref(publish.payload),
+            }),
+            Packet::Puback(pid) => BytesPacket::Puback(pid),
+            Packet::Pubrec(pid) => BytesPacket::Pubrec(pid),
+            Packet::Pubrel(pid) => BytesPacket::Pubrel(pid),
+            Packet::Pubcomp(pid) => BytesPacket::Pubcomp(pid),
+            Packet::Subscribe(subscribe) => BytesPacket::Subscribe(subscribe),
+            Packet::Suback(suback) => BytesPacket::Suback(suback),
+            Packet::Unsubscribe(unsubscribe) => BytesPacket::Unsubscribe(unsubscribe),
+            Packet::Unsuback(pid) => BytesPacket::Unsuback(pid),
+            Packet::Pingreq => BytesPacket::Pingreq,
+            Packet::Pingresp => BytesPacket::Pingresp,
+            Packet::Disconnect => BytesPacket::Disconnect,
+        }
+    }
+
+    /// Return the packet type variant.
+    pub fn get_type(&self) -> PacketType {
+        match self {
+            BytesPacket::Connect(_) => PacketType::Connect,
+            BytesPacket::Connack(_) => PacketType::Connack,
+            BytesPacket::Publish(_) => PacketType::Publish,
+            BytesPacket::Puback(_) => PacketType::Puback,
+            BytesPacket::Pubrec(_) => PacketType::Pubrec,
+            BytesPacket::Pubrel(_) => PacketType::Pubrel,
+            BytesPacket::Pubcomp(_) => PacketType::Pubcomp,
+            BytesPacket::Subscribe(_) => PacketType::Subscribe,
+            BytesPacket::Suback(_) => PacketType::Suback,
+            BytesPacket::Unsubscribe(_) => PacketType::Unsubscribe,
+            BytesPacket::Unsuback(_) => PacketType::Unsuback,
+            BytesPacket::Pingreq => PacketType::Pingreq,
+            BytesPacket::Pingresp => PacketType::Pingresp,
+            BytesPacket::Disconnect => PacketType::Disconnect,
+        }
+    }
+
+    /// Borrow as a [Packet], for example to encode it.
+    ///
+    /// Fails with `Error::InvalidString` if a string field isn't valid UTF-8, which can only
+    /// happen if it was modified after decoding. Subscribe, suback and unsubscribe packets are
+    /// cloned.
+    ///
+    /// [Packet]: enum.Packet.html
+    pub fn as_packet(&self) -> Result<Packet<'_>, Error> {
+        Ok(match self {
+            BytesPacket::Connect(connect) => connect.as_connect()?.into(),
+            BytesPacket::Connack(connack) => Packet::Connack(*connack),
+            BytesPacket::Publish(publish) => publish.as_publish()?.into(),
+            BytesPacket::Puback(pid) => Packet::Puback(*pid),
+            BytesPacket::Pubrec(pid) => Packet::Pubrec(*pid),
+            BytesPacket::Pubrel(pid) => Packet::Pubrel(*pid),
+            BytesPacket::Pubcomp(pid) => Packet::Pubcomp(*pid),
+            BytesPacket::Subscribe(subscribe) => Packet::Subscribe(subscribe.clone()),
+            BytesPacket::Suback(suback) => Packet::Suback(suback.clone()),
+            BytesPacket::Unsubscribe(unsubscribe) => Packet::Unsubscribe(unsubscribe.clone()),
+            BytesPacket::Unsuback(pid) => Packet::Unsuback(*pid),
+            BytesPacket::Pingreq => Packet::Pingreq,
+            BytesPacket::Pingresp => Packet::Pingresp,
+            BytesPacket::Disconnect => Packet::Disconnect,
+        })
+    }
+}
+
+/// Like [Connect], with [Bytes] strings and binary fields. String fields hold UTF-8.
+///
+/// [Connect]: struct.Connect.html
+/// [Bytes]: https://docs.rs/bytes/0.5.6/bytes/struct.Bytes.html
+#[derive(Debug, Clone, PartialEq)]
+pub struct BytesConnect {
+    pub protocol: Protocol,
+    pub keep_alive: u16,
+    pub client_id: Bytes,
+    pub clean_session: bool,
+    pub last_will: Option<BytesLastWill>,
+    pub username: Option<Bytes>,
+    pub password: Option<Bytes>,
+}
+
+impl BytesConnect {
+    /// Borrow as a [Connect], failing if a string field isn't valid UTF-8.
+    ///
+    /// [Connect]: struct.Connect.html
+    pub fn as_connect(&self) -> Result<Connect<'_>, Error> {
+        Ok(Connect {
+            protocol: self.protocol,
+            keep_alive: self.keep_alive,
+            client_id: from_utf8(&self.client_id).map_err(Error::InvalidString)?,
+            clean_session: self.clean_session,
+            last_will: match &self.last_will {
+                Some(will) => Some(LastWill {
+                    topic: from_utf8(&will.topic).map_err(Error::InvalidString)?,
+                    message: &will.message,
+                    qos: will.qos,
+                    retain: will.retain,
+                }),
+                None => None,
+            },
+            username: match &self.username {
+                Some(username) => Some(from_utf8(username).map_err(Error::InvalidString)?),
+                None => None,
+            },
+            password: self.password.as_deref(),
+        })
+    }
+}
+
+/// Like [LastWill], with [Bytes] topic and message. The topic holds UTF-8.
+///
+/// [LastWill]: struct.LastWill.html
+/// [Bytes]: https://docs.rs/bytes/0.5.6/bytes/struct.Bytes.html
+#[derive(Debug, Clone, PartialEq)]
+pub struct BytesLastWill {
+    pub topic: Bytes,
+    pub message: Bytes,
+    pub qos: QoS,
+    pub retain: bool,
+}
+
+/// Like [Publish], with [Bytes] topic and payload. The topic holds UTF-8.
+///
+/// [Publish]: struct.Publish.html
+/// [Bytes]: https://docs.rs/bytes/0.5.6/bytes/struct.Bytes.html
+#[derive(Debug, Clone, PartialEq)]
+pub struct BytesPublish {
+    pub dup: bool,
+    pub qospid: QosPid,
+    pub retain: bool,
+    pub topic_name: Bytes,
+    pub payload: Bytes,
+}
+
+impl BytesPublish {
+    /// The topic name, failing if it isn't valid UTF-8.
+    pub fn topic_str(&self) -> Result<&str, Error> {
+        from_utf8(&self.topic_name).map_err(Error::InvalidString)
+    }
+
+    /// Borrow as a [Publish], failing if the topic isn't valid UTF-8.
+    ///
+    /// [Publish]: struct.Publish.html
+    pub fn as_publish(&self) -> Result<Publish<'_>, Error> {
+        Ok(Publish {
+            dup: self.dup,
+            qospid: self.qospid,
+            retain: self.retain,
+            topic_name: self.topic_str()?,
+            payload: &self.payload,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+    use bytes::{Bytes, BytesMut};
+
+    #[test]
+    fn roundtrip() {
+        let connect: Packet = Connect {
+            protocol: Protocol::MQTT311,
+            keep_alive: 30,
+            client_id: "test",
+            clean_session: false,
+            last_will: Some(LastWill {
+                topic: "will",
+                message: b"gone",
+                qos: QoS::AtLeastOnce,
+                retain: true,
+            }),
+            username: Some("user"),
+            password: Some(b"pass"),
+        }
+        .into();
+        let publish: Packet = Publish {
+            dup: false,
+            qospid: QosPid::ExactlyOnce(Pid::new()),
+            retain: false,
+            topic_name: "a/b",
+            payload: b"payload",
+        }
+        .into();
+        let subscribe: Packet = Subscribe::new(
+            Pid::new(),
+            vec![SubscribeTopic {
+                topic_path: "a/#".into(),
+                qos: QoS::AtMostOnce,
+            }],
+        )
+        .into();
+
+        let mut buf = BytesMut::new();
+        for pkt in &[&connect, &publish, &subscribe, &Packet::Disconnect] {
+            let mut slice = [0u8; 64];
+            let len = encode_slice(pkt, &mut slice).unwrap();
+            buf.extend_from_slice(&slice[..len]);
+        }
+        let mut decoded = vec![];
+        while let Some(pkt) = decode_bytes_mut(&mut buf).unwrap() {
+            decoded.push(pkt);
+        }
+        assert!(buf.is_empty());
+        let packets: Vec<_> = decoded.iter().map(|p| p.as_packet().unwrap()).collect();
+        assert_eq!(
+            vec![connect, publish, subscribe, Packet::Disconnect],
+            packets
+        );
+        let types: Vec<_> = decoded.iter().map(BytesPacket::get_type).collect();
+        assert_eq!(
+            vec![
+                PacketType::Connect,
+                PacketType::Publish,
+                PacketType::Subscribe,
+                PacketType::Disconnect
+            ],
+            types
+        );
+    }
+
+    #[test]
+    fn invalid_topic() {
+        let publish = BytesPublish {
+            dup: false,
+            qospid: QosPid::AtMostOnce,
+            retain: false,
+            topic_name: Bytes::from_static(b"\xff"),
+            payload: Bytes::new(),
+        };
+        assert!(matches!(publish.as_publish(), Err(Error::InvalidString(_))));
+        let buf = Bytes::from_static(&[0b00110000, 0]);
+        assert_eq!(
+            Err(Error::FieldTooLong {
+                packet: PacketType::Publish,
+                offset: 2,
+                field: "topic",
+                len: 2,
+                remaining: 0
+            }),
+            decode_bytes(&buf)
+        );
+    }
+
+    #[test]
+    fn limit() {
+        let publish = [0b00110000, 4, 0, 1, b'a', b'b', 0b11000000, 0];
+        let buf = Bytes::copy_from_slice(&publish);
+        assert_eq!(
+            Err(Error::PacketTooLarge { size: 6, max: 5 }),
+            decode_bytes_with_limit(&buf, 5)
+        );
+        let (packet, len) = decode_bytes_with_limit(&buf, 6).unwrap().unwrap();
+        assert_eq!(len, 6);
+        assert_eq!(packet.get_type(), PacketType::Publish);
+
+        // Fails on the header alone, without touching the buffer
+        let mut buf = BytesMut::from(&publish[..2]);
+        assert_eq!(
+            Err(Error::PacketTooLarge { size: 6, max: 5 }),
+            decode_bytes_mut_with_limit(&mut buf, 5)
+        );
+        assert_eq!(Ok(None), decode_bytes_mut_with_limit(&mut buf, 6));
+        buf.extend_from_slice(&publish[2..]);
+        assert!(decode_bytes_mut_with_limit(&mut buf, 6).unwrap().is_some());
+        assert_eq!(
+            Ok(Some(BytesPacket::Pingreq)),
+            decode_bytes_mut_with_limit(&mut buf, 6)
+        );
+        assert!(buf.is_empty());
+    }
+}
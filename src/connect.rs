@@ -1,4 +1,8 @@
+#[cfg(feature = "defmt")]
+use crate::utils::BytesPrefix;
 use crate::{decoder::*, encoder::*, *};
+#[cfg(feature = "derive")]
+use serde::{Deserialize, Serialize};
 
 /// Protocol version.
 ///
@@ -6,6 +10,8 @@ use crate::{decoder::*, encoder::*, *};
 ///
 /// [`Connect`]: struct.Connect.html
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Protocol {
     /// [MQTT 3.1.1] is the most commonly implemented version. [MQTT 5] isn't yet supported my by
     /// `mqttrs`.
@@ -18,18 +24,26 @@ pub enum Protocol {
     /// standard MQTT 3.1.1.
     MQIsdp,
 }
+/// Longest protocol name kept by `Error::InvalidProtocol`, which can't grow without std.
+#[cfg(feature = "std")]
+const MAX_PROTOCOL_NAME: usize = usize::MAX;
+#[cfg(not(feature = "std"))]
+const MAX_PROTOCOL_NAME: usize = 10;
+
 impl Protocol {
     pub(crate) fn new(name: &str, level: u8) -> Result<Protocol, Error> {
         match (name, level) {
             ("MQIsdp", 3) => Ok(Protocol::MQIsdp),
             ("MQTT", 4) => Ok(Protocol::MQTT311),
-            _ => Err(Error::InvalidProtocol(name.into(), level)),
+            _ => Err(Error::InvalidProtocol(
+                crate::utils::truncate_str(name, MAX_PROTOCOL_NAME).into(),
+                level,
+            )),
         }
     }
-    pub(crate) fn from_buffer<'a>(buf: &'a [u8], offset: &mut usize) -> Result<Self, Error> {
-        let protocol_name = read_str(buf, offset)?;
-        let protocol_level = buf[*offset];
-        *offset += 1;
+    pub(crate) fn from_buffer(buf: &Input, offset: &mut usize) -> Result<Self, Error> {
+        let protocol_name = read_str(buf, offset, PacketType::Connect, "protocol_name")?;
+        let protocol_level = read_u8(buf, offset, PacketType::Connect, "protocol_level")?;
 
         Protocol::new(protocol_name, protocol_level)
     }
@@ -44,7 +58,7 @@ impl Protocol {
             }
             Protocol::MQIsdp => {
                 let slice = &[
-                    0u8, 4, 'M' as u8, 'Q' as u8, 'i' as u8, 's' as u8, 'd' as u8, 'p' as u8, 4,
+                    0u8, 6, 'M' as u8, 'Q' as u8, 'I' as u8, 's' as u8, 'd' as u8, 'p' as u8, 3,
                 ];
                 for &byte in slice {
                     write_u8(buf, offset, byte)?;
@@ -62,13 +76,29 @@ impl Protocol {
 /// [Connect]: struct.Connect.html
 /// [MQTT 3.1.3.3]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718031
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
 pub struct LastWill<'a> {
     pub topic: &'a str,
+    #[cfg_attr(feature = "derive", serde(borrow, with = "serde_bytes"))]
     pub message: &'a [u8],
     pub qos: QoS,
     pub retain: bool,
 }
 
+#[cfg(feature = "defmt")]
+impl defmt::Format for LastWill<'_> {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "LastWill {{ topic: {=str}, message: {}, qos: {}, retain: {} }}",
+            self.topic,
+            BytesPrefix(self.message),
+            self.qos,
+            self.retain
+        )
+    }
+}
+
 /// Sucess value of a [Connack] packet.
 ///
 /// See [MQTT 3.2.2.3] for interpretations.
@@ -76,6 +106,8 @@ pub struct LastWill<'a> {
 /// [Connack]: struct.Connack.html
 /// [MQTT 3.2.2.3]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718035
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum ConnectReturnCode {
     Accepted,
     RefusedProtocolVersion,
@@ -112,38 +144,61 @@ impl ConnectReturnCode {
 ///
 /// [MQTT 3.1]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718028
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
 pub struct Connect<'a> {
     pub protocol: Protocol,
     pub keep_alive: u16,
     pub client_id: &'a str,
     pub clean_session: bool,
+    #[cfg_attr(feature = "derive", serde(borrow))]
     pub last_will: Option<LastWill<'a>>,
+    #[cfg_attr(feature = "derive", serde(borrow))]
     pub username: Option<&'a str>,
+    #[cfg_attr(feature = "derive", serde(borrow, with = "serde_bytes"))]
     pub password: Option<&'a [u8]>,
 }
 
+#[cfg(feature = "defmt")]
+impl defmt::Format for Connect<'_> {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "Connect {{ protocol: {}, keep_alive: {}, client_id: {=str}, clean_session: {}, \
+             last_will: {}, username: {}, password: {} }}",
+            self.protocol,
+            self.keep_alive,
+            self.client_id,
+            self.clean_session,
+            self.last_will,
+            self.username,
+            self.password.map(BytesPrefix)
+        )
+    }
+}
+
 /// Connack packet ([MQTT 3.2]).
 ///
 /// [MQTT 3.2]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718033
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct Connack {
     pub session_present: bool,
     pub code: ConnectReturnCode,
 }
 
 impl<'a> Connect<'a> {
-    pub(crate) fn from_buffer(buf: &'a [u8], offset: &mut usize) -> Result<Self, Error> {
+    pub(crate) fn from_buffer(buf: &Input<'a>, offset: &mut usize) -> Result<Self, Error> {
         let protocol = Protocol::from_buffer(buf, offset)?;
 
-        let connect_flags = buf[*offset];
-        let keep_alive = ((buf[*offset + 1] as u16) << 8) | buf[*offset + 2] as u16;
-        *offset += 3;
+        let connect_flags = read_u8(buf, offset, PacketType::Connect, "connect_flags")?;
+        let keep_alive = read_u16(buf, offset, PacketType::Connect, "keep_alive")?;
 
-        let client_id = read_str(buf, offset)?;
+        let client_id = read_str(buf, offset, PacketType::Connect, "client_id")?;
 
         let last_will = if connect_flags & 0b100 != 0 {
-            let will_topic = read_str(buf, offset)?;
-            let will_message = read_bytes(buf, offset)?;
+            let will_topic = read_str(buf, offset, PacketType::Connect, "will_topic")?;
+            let will_message = read_bytes(buf, offset, PacketType::Connect, "will_message")?;
             let will_qod = QoS::from_u8((connect_flags & 0b11000) >> 3)?;
             Some(LastWill {
                 topic: will_topic,
@@ -156,13 +211,13 @@ impl<'a> Connect<'a> {
         };
 
         let username = if connect_flags & 0b10000000 != 0 {
-            Some(read_str(buf, offset)?)
+            Some(read_str(buf, offset, PacketType::Connect, "username")?)
         } else {
             None
         };
 
         let password = if connect_flags & 0b01000000 != 0 {
-            Some(read_bytes(buf, offset)?)
+            Some(read_bytes(buf, offset, PacketType::Connect, "password")?)
         } else {
             None
         };
@@ -180,24 +235,39 @@ impl<'a> Connect<'a> {
         })
     }
 
+    pub(crate) fn remaining_len(&self) -> Result<usize, Error> {
+        // protocol name and level
+        let mut length = match self.protocol {
+            Protocol::MQTT311 => 2 + 4 + 1,
+            Protocol::MQIsdp => 2 + 6 + 1,
+        };
+        length += 1 + 2; // flags + keep alive
+        length += string_len(self.client_id)?;
+        if let Some(last_will) = &self.last_will {
+            length += string_len(last_will.topic)?;
+            length += bytes_len(last_will.message)?;
+        };
+        if let Some(username) = self.username {
+            length += string_len(username)?;
+        };
+        if let Some(password) = self.password {
+            length += bytes_len(password)?;
+        };
+        Ok(length)
+    }
+
     pub(crate) fn to_buffer(&self, buf: &mut [u8], offset: &mut usize) -> Result<usize, Error> {
         let header: u8 = 0b00010000;
-        let mut length: usize = 6 + 1 + 1; // NOTE: protocol_name(6) + protocol_level(1) + flags(1);
+        let length = self.remaining_len()?;
         let mut connect_flags: u8 = 0b00000000;
         if self.clean_session {
             connect_flags |= 0b10;
         };
-        length += 2 + self.client_id.len();
-        length += 2; // keep alive
-        if let Some(username) = self.username {
+        if self.username.is_some() {
             connect_flags |= 0b10000000;
-            length += username.len();
-            length += 2;
         };
-        if let Some(password) = self.password {
+        if self.password.is_some() {
             connect_flags |= 0b01000000;
-            length += password.len();
-            length += 2;
         };
         if let Some(last_will) = &self.last_will {
             connect_flags |= 0b00000100;
@@ -205,9 +275,6 @@ impl<'a> Connect<'a> {
             if last_will.retain {
                 connect_flags |= 0b00100000;
             };
-            length += last_will.message.len();
-            length += last_will.topic.len();
-            length += 4;
         };
         check_remaining(buf, offset, length + 1)?;
 
@@ -239,10 +306,9 @@ impl<'a> Connect<'a> {
 }
 
 impl Connack {
-    pub(crate) fn from_buffer<'a>(buf: &'a [u8], offset: &mut usize) -> Result<Self, Error> {
-        let flags = buf[*offset];
-        let return_code = buf[*offset + 1];
-        *offset += 2;
+    pub(crate) fn from_buffer(buf: &Input, offset: &mut usize) -> Result<Self, Error> {
+        let flags = read_u8(buf, offset, PacketType::Connack, "connack_flags")?;
+        let return_code = read_u8(buf, offset, PacketType::Connack, "return_code")?;
         Ok(Connack {
             session_present: (flags & 0b1 == 1),
             code: ConnectReturnCode::from_u8(return_code)?,
@@ -1,4 +1,7 @@
-use crate::*;
+use crate::{utils::check_str, *};
+use core::cell::Cell;
+#[cfg(feature = "derive")]
+use serde::{Deserialize, Serialize};
 
 // use alloc::{string::String, vec::Vec};
 use heapless::{String, Vec, ArrayLength};
@@ -57,20 +60,250 @@ pub fn clone_packet(input: &[u8], output: &mut [u8]) -> Result<usize, Error> {
 }
 
 pub fn decode_slice<'a>(buf: &'a [u8]) -> Result<Option<Packet<'a>>, Error> {
+    decode_slice_with_limit(buf, usize::MAX)
+}
+
+/// Like [decode_slice()], but fails with `Error::PacketTooLarge` as soon as the fixed header
+/// announces a packet bigger than `max_packet_size` bytes.
+///
+/// The size includes the fixed header. Without a limit, a peer can announce up to 256MB and have
+/// you wait for (and buffer) all of it before the packet gets decoded.
+///
+/// ```
+/// # use mqttrs::*;
+/// // Publish header announcing 1000 bytes, only the first ones received so far.
+/// let buf = [0b00110000, 0xe8, 0x07, 0, 1, b'a'];
+/// assert_eq!(Ok(None), decode_slice(&buf));
+/// assert_eq!(
+///     Err(Error::PacketTooLarge { size: 1003, max: 256 }),
+///     decode_slice_with_limit(&buf, 256)
+/// );
+/// ```
+///
+/// [decode_slice()]: fn.decode_slice.html
+pub fn decode_slice_with_limit<'a>(
+    buf: &'a [u8],
+    max_packet_size: usize,
+) -> Result<Option<Packet<'a>>, Error> {
+    Ok(decode_with_len(buf, max_packet_size)?.map(|(packet, _)| packet))
+}
+
+/// Decode the packet at the start of `buf`, returning it with its encoded length.
+pub(crate) fn decode_with_len<'a>(
+    buf: &'a [u8],
+    max_packet_size: usize,
+) -> Result<Option<(Packet<'a>, usize)>, Error> {
     let mut offset = 0;
-    if let Some((header, remaining_len)) = read_header(buf, &mut offset)? {
-        let r = read_packet(header, remaining_len, buf, &mut offset)?;
-        Ok(Some(r))
+    if let Some((header, remaining_len)) =
+        read_header_with_limit(buf, &mut offset, max_packet_size)?
+    {
+        let len = offset + remaining_len;
+        let r = read_packet(header, remaining_len, &Input::new(&buf[..len]), &mut offset)?;
+        Ok(Some((r, len)))
     } else {
         // Don't have a full packet
         Ok(None)
     }
 }
 
+/// Iterator over the complete packets of a buffer, typically filled by a large `read()`.
+///
+/// It stops at a partial trailing packet, which [remaining()] returns so that it can be completed
+/// by the next read. After an error, the iterator stops and [remaining()] starts with the packet
+/// that failed to decode.
+///
+/// ```
+/// # use mqttrs::*;
+/// // A Pingreq, a Puback, and the first byte of a Pingresp.
+/// let buf = [0b11000000, 0, 0b01000000, 2, 0, 10, 0b11010000];
+/// let mut iter = PacketIter::new(&buf);
+/// assert_eq!(Some(Ok(Packet::Pingreq)), iter.next());
+/// assert!(matches!(iter.next(), Some(Ok(Packet::Puback(_)))));
+/// assert_eq!(None, iter.next());
+/// assert_eq!(&[0b11010000], iter.remaining());
+/// ```
+///
+/// [remaining()]: #method.remaining
+#[derive(Debug, Clone)]
+pub struct PacketIter<'a> {
+    buf: &'a [u8],
+    max_packet_size: usize,
+    failed: bool,
+}
+
+impl<'a> PacketIter<'a> {
+    /// Iterate over the packets of `buf`.
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self::with_limit(buf, usize::MAX)
+    }
+
+    /// Iterate over the packets of `buf`, failing like [decode_slice_with_limit()] on packets
+    /// bigger than `max_packet_size`.
+    ///
+    /// [decode_slice_with_limit()]: fn.decode_slice_with_limit.html
+    pub fn with_limit(buf: &'a [u8], max_packet_size: usize) -> Self {
+        PacketIter {
+            buf,
+            max_packet_size,
+            failed: false,
+        }
+    }
+
+    /// The bytes that haven't been decoded yet.
+    pub fn remaining(&self) -> &'a [u8] {
+        self.buf
+    }
+}
+
+impl<'a> Iterator for PacketIter<'a> {
+    type Item = Result<Packet<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        match decode_with_len(self.buf, self.max_packet_size) {
+            Ok(Some((packet, len))) => {
+                self.buf = &self.buf[len..];
+                Some(Ok(packet))
+            }
+            Ok(None) => None,
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+impl core::iter::FusedIterator for PacketIter<'_> {}
+
+/// Like [decode_slice()], for a server decoding what a client sent: fails with
+/// `Error::WrongDirection` on packets that only a server may send, like `Connack` or `Suback`.
+///
+/// The packet type is checked on the first byte, without waiting for the rest of the packet.
+///
+/// ```
+/// # use mqttrs::*;
+/// let buf = [0b11000000, 0];
+/// assert_eq!(Ok(Some(Packet::Pingreq)), decode_from_client(&buf));
+/// assert_eq!(Err(Error::WrongDirection(PacketType::Pingreq)), decode_from_server(&buf));
+/// ```
+///
+/// [decode_slice()]: fn.decode_slice.html
+pub fn decode_from_client<'a>(buf: &'a [u8]) -> Result<Option<Packet<'a>>, Error> {
+    check_direction(buf, PacketType::sent_by_client)?;
+    decode_slice(buf)
+}
+
+/// Like [decode_slice()], for a client decoding what the server sent: fails with
+/// `Error::WrongDirection` on packets that only a client may send, like `Connect` or `Subscribe`.
+///
+/// The packet type is checked on the first byte, without waiting for the rest of the packet.
+///
+/// [decode_slice()]: fn.decode_slice.html
+pub fn decode_from_server<'a>(buf: &'a [u8]) -> Result<Option<Packet<'a>>, Error> {
+    check_direction(buf, PacketType::sent_by_server)?;
+    decode_slice(buf)
+}
+
+/// Like [decode_slice()], for a packet that may wrap around the end of a ring buffer: the received
+/// bytes are `first` followed by `second`.
+///
+/// Fields that lie within `first` or `second` are borrowed as usual, only those that straddle both
+/// get copied into `scratch`. Fails with `Error::BufferTooSmall` if they don't fit. Returns the
+/// packet with its encoded length, to advance the ring buffer's read position.
+///
+/// Most fields are short strings, but a publish payload is one field: when it straddles the wrap,
+/// `scratch` must be as large as the whole payload. To always succeed, `scratch` must be as large
+/// as the biggest packet accepted, see [decode_split_with_limit()].
+///
+/// ```
+/// # use mqttrs::*;
+/// // Publish to "a/b" with payload "hello", wrapped in the middle of the topic.
+/// let first = [0b00110000, 10, 0, 3, b'a'];
+/// let second = [b'/', b'b', b'h', b'e', b'l', b'l', b'o'];
+/// let mut scratch = [0u8; 8];
+/// match decode_split(&first, &second, &mut scratch) {
+///     Ok(Some((Packet::Publish(p), len))) => {
+///         assert_eq!(p.topic_name, "a/b");
+///         assert_eq!(p.payload, b"hello");
+///         assert_eq!(p.payload.as_ptr(), second[2..].as_ptr());
+///         assert_eq!(len, 12);
+///     }
+///     other => panic!("unexpected {:?}", other),
+/// }
+/// ```
+///
+/// [decode_slice()]: fn.decode_slice.html
+/// [decode_split_with_limit()]: fn.decode_split_with_limit.html
+pub fn decode_split<'a>(
+    first: &'a [u8],
+    second: &'a [u8],
+    scratch: &'a mut [u8],
+) -> Result<Option<(Packet<'a>, usize)>, Error> {
+    decode_split_with_limit(first, second, scratch, usize::MAX)
+}
+
+/// Like [decode_split()], but fails with `Error::PacketTooLarge` as soon as the fixed header
+/// announces a packet bigger than `max_packet_size` bytes, like [decode_slice_with_limit()].
+///
+/// A `scratch` of `max_packet_size` bytes is enough for any packet that gets decoded.
+///
+/// [decode_split()]: fn.decode_split.html
+/// [decode_slice_with_limit()]: fn.decode_slice_with_limit.html
+pub fn decode_split_with_limit<'a>(
+    first: &'a [u8],
+    second: &'a [u8],
+    scratch: &'a mut [u8],
+    max_packet_size: usize,
+) -> Result<Option<(Packet<'a>, usize)>, Error> {
+    // The fixed header is at most 5 bytes long
+    let mut header = [0u8; 5];
+    let mut header_bytes = 0;
+    for (dst, src) in header.iter_mut().zip(first.iter().chain(second)) {
+        *dst = *src;
+        header_bytes += 1;
+    }
+    let (header, header_len, remaining_len) = match peek_header(&header[..header_bytes])? {
+        Some(h) => h,
+        None => return Ok(None),
+    };
+    let len = header_len + remaining_len;
+    check_packet_size(len, max_packet_size)?;
+    if first.len() + second.len() < len {
+        // Don't have a full packet
+        return Ok(None);
+    }
+    let first = &first[..len.min(first.len())];
+    let buf = Input {
+        first,
+        second: &second[..len - first.len()],
+        scratch: Cell::new(scratch),
+    };
+    let mut offset = header_len;
+    let packet = read_packet(header, remaining_len, &buf, &mut offset)?;
+    Ok(Some((packet, len)))
+}
+
+fn check_direction(buf: &[u8], allowed: fn(PacketType) -> bool) -> Result<(), Error> {
+    match buf.first() {
+        Some(&hd) => {
+            let typ = FixedHeader::from_byte(hd)?.typ;
+            if allowed(typ) {
+                Ok(())
+            } else {
+                Err(Error::WrongDirection(typ))
+            }
+        }
+        None => Ok(()),
+    }
+}
+
 fn read_packet<'a>(
-    header: Header,
+    header: FixedHeader,
     remaining_len: usize,
-    buf: &'a [u8],
+    buf: &Input<'a>,
     offset: &mut usize,
 ) -> Result<Packet<'a>, Error> {
     Ok(match header.typ {
@@ -80,38 +313,91 @@ fn read_packet<'a>(
         PacketType::Connect => Connect::from_buffer(buf, offset)?.into(),
         PacketType::Connack => Connack::from_buffer(buf, offset)?.into(),
         PacketType::Publish => Publish::from_buffer(&header, remaining_len, buf, offset)?.into(),
-        PacketType::Puback => Packet::Puback(Pid::from_buffer(buf, offset)?),
-        PacketType::Pubrec => Packet::Pubrec(Pid::from_buffer(buf, offset)?),
-        PacketType::Pubrel => Packet::Pubrel(Pid::from_buffer(buf, offset)?),
-        PacketType::Pubcomp => Packet::Pubcomp(Pid::from_buffer(buf, offset)?),
+        PacketType::Puback => Packet::Puback(Pid::from_buffer(buf, offset, header.typ)?),
+        PacketType::Pubrec => Packet::Pubrec(Pid::from_buffer(buf, offset, header.typ)?),
+        PacketType::Pubrel => Packet::Pubrel(Pid::from_buffer(buf, offset, header.typ)?),
+        PacketType::Pubcomp => Packet::Pubcomp(Pid::from_buffer(buf, offset, header.typ)?),
         PacketType::Subscribe => Subscribe::from_buffer(remaining_len, buf, offset)?.into(),
         PacketType::Suback => Suback::from_buffer(remaining_len, buf, offset)?.into(),
         PacketType::Unsubscribe => Unsubscribe::from_buffer(remaining_len, buf, offset)?.into(),
-        PacketType::Unsuback => Packet::Unsuback(Pid::from_buffer(buf, offset)?),
+        PacketType::Unsuback => Packet::Unsuback(Pid::from_buffer(buf, offset, header.typ)?),
     })
 }
 
+/// Read the fixed header at the start of `buf`, without waiting for the rest of the packet.
+///
+/// Returns the header, the number of bytes it was encoded with (the type byte and the remaining
+/// length), and the remaining length. The whole packet is `header_len + remaining_len` bytes long.
+/// Returns `None` if `buf` doesn't contain the whole header yet.
+///
+/// ```
+/// # use mqttrs::*;
+/// // Publish header announcing 1000 bytes, only the first ones received so far.
+/// let buf = [0b00110010, 0xe8, 0x07, 0, 1, b'a'];
+/// let (header, header_len, remaining_len) = peek_header(&buf).unwrap().unwrap();
+/// assert_eq!(header.typ, PacketType::Publish);
+/// assert_eq!(header.qos, QoS::AtLeastOnce);
+/// assert_eq!((header_len, remaining_len), (3, 1000));
+/// assert_eq!(Ok(None), peek_header(&buf[..2]));
+/// ```
+pub fn peek_header(buf: &[u8]) -> Result<Option<(FixedHeader, usize, usize)>, Error> {
+    let header = match buf.first() {
+        Some(&hd) => FixedHeader::from_byte(hd)?,
+        None => return Ok(None),
+    };
+    Ok(read_length(buf, 0)?.map(|(len, len_len)| (header, 1 + len_len, len)))
+}
+
 /// Read the parsed header and remaining_len from the buffer. Only return Some() and advance the
 /// buffer position if there is enough data in the buffer to read the full packet.
 pub(crate) fn read_header<'a>(
     buf: &'a [u8],
     offset: &mut usize,
-) -> Result<Option<(Header, usize)>, Error> {
+) -> Result<Option<(FixedHeader, usize)>, Error> {
+    read_header_with_limit(buf, offset, usize::MAX)
+}
+
+/// Like `read_header()`, failing if the packet is bigger than `max_packet_size`.
+fn read_header_with_limit(
+    buf: &[u8],
+    offset: &mut usize,
+    max_packet_size: usize,
+) -> Result<Option<(FixedHeader, usize)>, Error> {
+    if let Some((len, len_len)) = read_length(buf, *offset)? {
+        check_packet_size(1 + len_len + len, max_packet_size)?;
+        if buf.len() < *offset + 1 + len_len + len {
+            // Won't be able to read full packet
+            return Ok(None);
+        }
+        // Parse header byte, skip past the header, and return
+        let header = FixedHeader::from_byte(buf[*offset])?;
+        *offset += len_len + 1;
+        Ok(Some((header, len)))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Fail if a packet of `size` bytes (including the fixed header) is bigger than the limit.
+pub(crate) fn check_packet_size(size: usize, max: usize) -> Result<(), Error> {
+    if size > max {
+        Err(Error::PacketTooLarge { size, max })
+    } else {
+        Ok(())
+    }
+}
+
+/// Read the remaining_len following the header byte at `offset`. Returns the length and the
+/// number of bytes it was encoded with, or None if the buffer doesn't contain the whole length.
+pub(crate) fn read_length(buf: &[u8], offset: usize) -> Result<Option<(usize, usize)>, Error> {
     let mut len: usize = 0;
     for pos in 0..=3 {
-        if buf.len() > *offset + pos + 1 {
-            let byte = buf[*offset + pos + 1];
+        if buf.len() > offset + pos + 1 {
+            let byte = buf[offset + pos + 1];
             len += (byte as usize & 0x7F) << (pos * 7);
             if (byte & 0x80) == 0 {
                 // Continuation bit == 0, length is parsed
-                if buf.len() < *offset + 2 + pos + len {
-                    // Won't be able to read full packet
-                    return Ok(None);
-                }
-                // Parse header byte, skip past the header, and return
-                let header = Header::new(buf[*offset])?;
-                *offset += pos + 2;
-                return Ok(Some((header, len)));
+                return Ok(Some((len, pos + 1)));
             }
         } else {
             // Couldn't read full length
@@ -119,18 +405,45 @@ pub(crate) fn read_header<'a>(
         }
     }
     // Continuation byte == 1 four times, that's illegal.
-    Err(Error::InvalidHeader)
+    Err(Error::InvalidRemainingLength)
 }
 
+/// The first byte of a packet: its type and flags.
+///
+/// `dup`, `qos` and `retain` are only meaningful for publish packets. Pubrel, subscribe and
+/// unsubscribe packets have a fixed `qos` of `AtLeastOnce`, all the other flags are zero. Use
+/// [peek_header()] to read one from a buffer, and [encode_fixed_header()] to write one.
+///
+/// [peek_header()]: fn.peek_header.html
+/// [encode_fixed_header()]: fn.encode_fixed_header.html
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub(crate) struct Header {
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FixedHeader {
     pub typ: PacketType,
     pub dup: bool,
     pub qos: QoS,
     pub retain: bool,
 }
-impl Header {
-    pub fn new(hd: u8) -> Result<Header, Error> {
+impl FixedHeader {
+    /// Header of a packet type, with the flags required by that type.
+    pub fn new(typ: PacketType) -> Self {
+        let qos = match typ {
+            PacketType::Pubrel | PacketType::Subscribe | PacketType::Unsubscribe => {
+                QoS::AtLeastOnce
+            }
+            _ => QoS::AtMostOnce,
+        };
+        FixedHeader {
+            typ,
+            dup: false,
+            qos,
+            retain: false,
+        }
+    }
+
+    /// Parse the first byte of a packet, failing on an invalid packet type or flags.
+    pub fn from_byte(hd: u8) -> Result<FixedHeader, Error> {
         let (typ, flags_ok) = match hd >> 4 {
             1 => (PacketType::Connect, hd & 0b1111 == 0),
             2 => (PacketType::Connack, hd & 0b1111 == 0),
@@ -146,34 +459,180 @@ impl Header {
             12 => (PacketType::Pingreq, hd & 0b1111 == 0),
             13 => (PacketType::Pingresp, hd & 0b1111 == 0),
             14 => (PacketType::Disconnect, hd & 0b1111 == 0),
-            _ => (PacketType::Connect, false),
+            n => return Err(Error::InvalidPacketType(n)),
         };
         if !flags_ok {
-            return Err(Error::InvalidHeader);
+            return Err(Error::InvalidFlags(typ, hd & 0b1111));
         }
-        Ok(Header {
+        Ok(FixedHeader {
             typ,
             dup: hd & 0b1000 != 0,
             qos: QoS::from_u8((hd & 0b110) >> 1)?,
             retain: hd & 1 == 1,
         })
     }
+
+    /// The first byte of a packet with this header.
+    ///
+    /// This doesn't check that the flags are valid for the packet type, [encode_fixed_header()]
+    /// does.
+    ///
+    /// [encode_fixed_header()]: fn.encode_fixed_header.html
+    pub fn to_byte(&self) -> u8 {
+        let typ = match self.typ {
+            PacketType::Connect => 1,
+            PacketType::Connack => 2,
+            PacketType::Publish => 3,
+            PacketType::Puback => 4,
+            PacketType::Pubrec => 5,
+            PacketType::Pubrel => 6,
+            PacketType::Pubcomp => 7,
+            PacketType::Subscribe => 8,
+            PacketType::Suback => 9,
+            PacketType::Unsubscribe => 10,
+            PacketType::Unsuback => 11,
+            PacketType::Pingreq => 12,
+            PacketType::Pingresp => 13,
+            PacketType::Disconnect => 14,
+        };
+        typ << 4 | (self.dup as u8) << 3 | self.qos.to_u8() << 1 | self.retain as u8
+    }
 }
 
-pub(crate) fn read_str<'a>(buf: &'a [u8], offset: &mut usize) -> Result<&'a str, Error> {
-    core::str::from_utf8(read_bytes(buf, offset)?).map_err(|e| Error::InvalidString(e))
+/// The bytes of one packet, in one part or split in two, like at the end of a ring buffer.
+pub(crate) struct Input<'a> {
+    first: &'a [u8],
+    second: &'a [u8],
+    /// Where the fields that straddle both parts get copied.
+    scratch: Cell<&'a mut [u8]>,
+}
+
+impl<'a> Input<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Input {
+            first: buf,
+            second: &[],
+            scratch: Cell::new(&mut []),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.first.len() + self.second.len()
+    }
+
+    /// The byte at `index`, which must be less than `len()`.
+    pub fn byte(&self, index: usize) -> u8 {
+        match self.first.get(index) {
+            Some(&b) => b,
+            None => self.second[index - self.first.len()],
+        }
+    }
+
+    /// The bytes from `start` to `end`, which must not be more than `len()`.
+    ///
+    /// Fails with `Error::BufferTooSmall` if they straddle both parts and don't fit in the
+    /// remaining scratch space.
+    pub fn slice(&self, start: usize, end: usize) -> Result<&'a [u8], Error> {
+        let split = self.first.len();
+        if end <= split {
+            return Ok(&self.first[start..end]);
+        }
+        if start >= split {
+            return Ok(&self.second[start - split..end - split]);
+        }
+        let scratch = self.scratch.take();
+        if scratch.len() < end - start {
+            let available = scratch.len();
+            self.scratch.set(scratch);
+            return Err(Error::BufferTooSmall {
+                required: end - start,
+                available,
+            });
+        }
+        let (copy, rest) = scratch.split_at_mut(end - start);
+        self.scratch.set(rest);
+        copy[..split - start].copy_from_slice(&self.first[start..]);
+        copy[split - start..].copy_from_slice(&self.second[..end - split]);
+        Ok(copy)
+    }
 }
 
-pub(crate) fn read_bytes<'a>(buf: &'a [u8], offset: &mut usize) -> Result<&'a [u8], Error> {
-    if buf[*offset..].len() < 2 {
-        return Err(Error::InvalidLength);
+/// Check that a `len` bytes long `field` starting at `offset` fits in the packet.
+///
+/// `buf` must end where the packet ends.
+pub(crate) fn check_field(
+    buf: &Input,
+    offset: usize,
+    len: usize,
+    packet: PacketType,
+    field: &'static str,
+) -> Result<(), Error> {
+    let remaining = buf.len().saturating_sub(offset);
+    if len > remaining {
+        Err(Error::FieldTooLong {
+            packet,
+            offset,
+            field,
+            len,
+            remaining,
+        })
+    } else {
+        Ok(())
     }
-    let len = ((buf[*offset] as usize) << 8) | buf[*offset + 1] as usize;
+}
+
+pub(crate) fn read_u8(
+    buf: &Input,
+    offset: &mut usize,
+    packet: PacketType,
+    field: &'static str,
+) -> Result<u8, Error> {
+    check_field(buf, *offset, 1, packet, field)?;
+    *offset += 1;
+    Ok(buf.byte(*offset - 1))
+}
+
+pub(crate) fn read_u16(
+    buf: &Input,
+    offset: &mut usize,
+    packet: PacketType,
+    field: &'static str,
+) -> Result<u16, Error> {
+    check_field(buf, *offset, 2, packet, field)?;
     *offset += 2;
-    if len > buf[*offset..].len() {
-        Err(Error::InvalidLength)
+    Ok(((buf.byte(*offset - 2) as u16) << 8) | buf.byte(*offset - 1) as u16)
+}
+
+pub(crate) fn read_str<'a>(
+    buf: &Input<'a>,
+    offset: &mut usize,
+    packet: PacketType,
+    field: &'static str,
+) -> Result<&'a str, Error> {
+    let s = core::str::from_utf8(read_bytes(buf, offset, packet, field)?)
+        .map_err(Error::InvalidString)?;
+    check_str(s)?;
+    Ok(s)
+}
+
+pub(crate) fn read_bytes<'a>(
+    buf: &Input<'a>,
+    offset: &mut usize,
+    packet: PacketType,
+    field: &'static str,
+) -> Result<&'a [u8], Error> {
+    let start = *offset;
+    let len = read_u16(buf, offset, packet, field)? as usize;
+    if len > buf.len() - *offset {
+        Err(Error::FieldTooLong {
+            packet,
+            offset: start,
+            field,
+            len,
+            remaining: buf.len() - *offset,
+        })
     } else {
-        let bytes = &buf[*offset..*offset + len];
+        let bytes = buf.slice(*offset, *offset + len)?;
         *offset += len;
         Ok(bytes)
     }
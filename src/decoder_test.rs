@@ -4,7 +4,7 @@ use subscribe::LimitedString;
 
 macro_rules! header {
     ($t:ident, $d:expr, $q:ident, $r:expr) => {
-        decoder::Header {
+        decoder::FixedHeader {
             typ: PacketType::$t,
             dup: $d,
             qos: QoS::$q,
@@ -51,7 +51,10 @@ fn header_firstbyte() {
         let res = match valid.iter().find(|(byte, _)| *byte == n) {
             Some((_, header)) => Ok(Some((*header, 0))),
             None if ((n & 0b110) == 0b110) && (n >> 4 == 3) => Err(Error::InvalidQos(3)),
-            None => Err(Error::InvalidHeader),
+            None => match valid.iter().find(|(byte, _)| byte >> 4 == n >> 4) {
+                Some((_, header)) => Err(Error::InvalidFlags(header.typ, n & 0b1111)),
+                None => Err(Error::InvalidPacketType(n >> 4)),
+            },
         };
         let mut buf: &[u8] = &[n, 0];
         let mut offset = 0;
@@ -66,6 +69,11 @@ fn header_firstbyte() {
         } else {
             assert_eq!(offset, 0);
         }
+        let peeked = peek_header(buf).map(|o| o.map(|(header, _, len)| (header, len)));
+        assert_eq!(res, peeked, "{:08b}", n);
+        if let Ok(Some((header, _))) = res {
+            assert_eq!(n, header.to_byte());
+        }
     }
 }
 
@@ -83,7 +91,7 @@ fn header_len() {
         (Ok(Some((h, 128))),        vec![1 << 4, 0x80, 1], 131),
         (Ok(None),                  vec![1 << 4, 0x80+16, 78], 10002),
         (Ok(Some((h, 10000))),      vec![1 << 4, 0x80+16, 78], 10003),
-        (Err(Error::InvalidHeader), vec![1 << 4, 0x80, 0x80, 0x80, 0x80], 10),
+        (Err(Error::InvalidRemainingLength), vec![1 << 4, 0x80, 0x80, 0x80, 0x80], 10),
     ] {
         let offset_expectation = bytes.len();
         bytes.resize(buflen, 0);
@@ -110,6 +118,21 @@ fn non_utf8_string() {
     });
 }
 
+#[test]
+fn disallowed_chars() {
+    // Publish with topic "a\0"
+    let data: &[u8] = &[0b00110000, 4, 0, 2, b'a', 0];
+    assert_eq!(Err(Error::InvalidChar('\0')), decode_slice(data));
+    // Subscribe with topic "a\u{7f}"
+    let data: &[u8] = &[0b10000010, 7, 0, 1, 0, 2, b'a', 0x7f, 0];
+    assert_eq!(Err(Error::InvalidChar('\u{7f}')), decode_slice(data));
+    // Connect with client_id "\u{fdd0}"
+    let data: &[u8] = &[
+        0b00010000, 15, 0, 4, b'M', b'Q', b'T', b'T', 4, 0, 0, 10, 0, 3, 0xef, 0xb7, 0x90,
+    ];
+    assert_eq!(Err(Error::InvalidChar('\u{fdd0}')), decode_slice(data));
+}
+
 /// Validity of remaining_len is tested exhaustively elsewhere, this is for inner lengths, which
 /// are rarer.
 #[test]
@@ -121,7 +144,18 @@ fn inner_length_too_long() {
         0x00, 0x04, 't' as u8, 'e' as u8, 's' as u8, 't' as u8, // client_id
         0x00, 0x03, 'm' as u8, 'q' as u8, // password with invalid length
     ]);
-    assert_eq!(Err(Error::InvalidLength), decode_slice(&mut data));
+    let err = Error::FieldTooLong {
+        packet: PacketType::Connect,
+        offset: 18,
+        field: "password",
+        len: 3,
+        remaining: 2,
+    };
+    assert_eq!(Err(err.clone()), decode_slice(&mut data));
+    assert_eq!(
+        "CONNECT at offset 18: password length 3 exceeds remaining 2",
+        format!("{}", err)
+    );
 
     let mut slice: &[u8] = &[
         0b00010000, 20, // Connect packet, remaining_len=20
@@ -131,10 +165,212 @@ fn inner_length_too_long() {
         0x00, 0x03, 'm' as u8, 'q' as u8, // password with invalid length
     ];
 
-    assert_eq!(Err(Error::InvalidLength), decode_slice(&mut slice));
+    assert_eq!(Err(err), decode_slice(&mut slice));
     // assert_eq!(slice, []);
 }
 
+#[test]
+fn packet_too_large() {
+    let buf = [0b01000000, 2, 0, 10];
+    let pkt = Packet::Puback(Pid::new() + 9);
+    assert_eq!(Ok(Some(pkt)), decode_slice_with_limit(&buf, 4));
+    assert_eq!(
+        Err(Error::PacketTooLarge { size: 4, max: 3 }),
+        decode_slice_with_limit(&buf, 3)
+    );
+    // Rejected as soon as the remaining length is known
+    assert_eq!(
+        Err(Error::PacketTooLarge { size: 4, max: 3 }),
+        decode_slice_with_limit(&buf[..2], 3)
+    );
+    assert_eq!(Ok(None), decode_slice_with_limit(&buf[..1], 3));
+}
+
+#[test]
+fn packet_iter() {
+    let mut buf = [0u8; 64];
+    let mut len = 0;
+    let publish = Packet::from(Publish {
+        dup: false,
+        qospid: QosPid::AtLeastOnce(Pid::new()),
+        retain: false,
+        topic_name: "a/b",
+        payload: b"hello",
+    });
+    for pkt in &[Packet::Pingreq, publish.clone(), Packet::Puback(Pid::new())] {
+        len += encode_slice(pkt, &mut buf[len..]).unwrap();
+    }
+    let packets: Vec<_> = PacketIter::new(&buf[..len]).collect();
+    assert_eq!(
+        vec![
+            Ok(Packet::Pingreq),
+            Ok(publish),
+            Ok(Packet::Puback(Pid::new()))
+        ],
+        packets
+    );
+
+    // Stops at any truncation of the last packet
+    assert_eq!(20, len);
+    for end in 2..len {
+        let mut iter = PacketIter::new(&buf[..end]);
+        assert_eq!(Some(Ok(Packet::Pingreq)), iter.next());
+        assert_eq!(end >= 16, iter.next().is_some());
+        assert_eq!(None, iter.next());
+        assert_eq!(&buf[if end >= 16 { 16 } else { 2 }..end], iter.remaining());
+    }
+
+    // Stops after an error, at the packet that failed
+    let mut iter = PacketIter::with_limit(&buf[..len], 10);
+    assert_eq!(Some(Ok(Packet::Pingreq)), iter.next());
+    assert_eq!(
+        Some(Err(Error::PacketTooLarge { size: 14, max: 10 })),
+        iter.next()
+    );
+    assert_eq!(None, iter.next());
+    assert_eq!(&buf[2..len], iter.remaining());
+    assert_eq!(0, PacketIter::new(&[]).count());
+}
+
+#[test]
+fn split() {
+    let connect: Packet = Connect {
+        protocol: Protocol::MQTT311,
+        keep_alive: 30,
+        client_id: "test",
+        clean_session: true,
+        last_will: Some(LastWill {
+            topic: "will",
+            message: b"gone",
+            qos: QoS::AtMostOnce,
+            retain: false,
+        }),
+        username: Some("user"),
+        password: Some(b"pass"),
+    }
+    .into();
+    let publish: Packet = Publish {
+        dup: false,
+        qospid: QosPid::AtLeastOnce(Pid::new()),
+        retain: false,
+        topic_name: "a/b",
+        payload: &[7; 200],
+    }
+    .into();
+    let subscribe: Packet = Subscribe::new(
+        Pid::new(),
+        vec![SubscribeTopic {
+            topic_path: "a/#".into(),
+            qos: QoS::ExactlyOnce,
+        }],
+    )
+    .into();
+    for pkt in &[connect, publish, subscribe, Packet::Pingreq] {
+        let mut buf = [0u8; 256];
+        let len = encode_slice(pkt, &mut buf).unwrap();
+        for split in 0..=len {
+            let (first, second) = buf[..len].split_at(split);
+            let mut scratch = [0u8; 256];
+            assert_eq!(
+                Ok(Some((pkt.clone(), len))),
+                decode_split(first, second, &mut scratch),
+                "{} split at {}",
+                pkt,
+                split
+            );
+            if split < len {
+                assert_eq!(Ok(None), decode_split(first, &second[..0], &mut scratch));
+            }
+        }
+    }
+
+    // Only the fields that straddle need scratch space
+    let buf = [
+        0b00110000, 10, 0, 3, b'a', b'/', b'b', b'h', b'e', b'l', b'l', b'o',
+    ];
+    assert!(decode_split(&buf[..4], &buf[4..], &mut []).is_ok());
+    assert!(decode_split(&buf[..7], &buf[7..], &mut []).is_ok());
+    assert!(decode_split(&buf[..9], &buf[9..], &mut [0; 5]).is_ok());
+    assert_eq!(
+        Err(Error::BufferTooSmall {
+            required: 3,
+            available: 2
+        }),
+        decode_split(&buf[..5], &buf[5..], &mut [0; 2])
+    );
+
+    // The limit applies as soon as the fixed header is received, even split
+    assert_eq!(
+        Err(Error::PacketTooLarge { size: 12, max: 11 }),
+        decode_split_with_limit(&buf[..1], &buf[1..2], &mut [], 11)
+    );
+    assert!(decode_split_with_limit(&buf[..9], &buf[9..], &mut [0; 12], 12).is_ok());
+}
+
+#[test]
+fn direction() {
+    let connack = [0b00100000, 2, 0, 0];
+    let puback = [0b01000000, 2, 0, 10];
+    let subscribe = [0b10000010, 8, 0, 10, 0, 3, b'a', b'/', b'b', 0];
+    assert!(decode_from_server(&connack).unwrap().is_some());
+    assert!(decode_from_client(&subscribe).unwrap().is_some());
+    assert_eq!(
+        Err(Error::WrongDirection(PacketType::Connack)),
+        decode_from_client(&connack)
+    );
+    assert_eq!(
+        Err(Error::WrongDirection(PacketType::Subscribe)),
+        decode_from_server(&subscribe)
+    );
+    // Acks go both ways
+    let pkt = Packet::Puback(Pid::new() + 9);
+    assert_eq!(Ok(Some(pkt.clone())), decode_from_client(&puback));
+    assert_eq!(Ok(Some(pkt)), decode_from_server(&puback));
+    // Rejected on the first byte, and still fine with an empty buffer
+    assert_eq!(
+        Err(Error::WrongDirection(PacketType::Subscribe)),
+        decode_from_server(&subscribe[..1])
+    );
+    assert_eq!(Ok(None), decode_from_server(&[]));
+    assert_eq!(
+        "SUBSCRIBE can only be sent by a client",
+        Error::WrongDirection(PacketType::Subscribe).to_string()
+    );
+}
+
+/// Fields are bounded by the remaining length, not by the buffer, which may hold more packets.
+#[test]
+fn field_past_remaining_length() {
+    // Puback without pid, followed by a Pingreq
+    assert_eq!(
+        Err(Error::FieldTooLong {
+            packet: PacketType::Puback,
+            offset: 2,
+            field: "pid",
+            len: 2,
+            remaining: 0,
+        }),
+        decode_slice(&[0b01000000, 0, 0b11000000, 0])
+    );
+    assert_eq!(
+        Err(Error::FieldTooLong {
+            packet: PacketType::Connack,
+            offset: 3,
+            field: "return_code",
+            len: 1,
+            remaining: 0,
+        }),
+        decode_slice(&[0b00100000, 1, 0])
+    );
+
+    // Subscribe with a 300 bytes topic
+    let err = decode_slice(&[0b10000010, 5, 0, 1, 0x01, 0x2c, b'a']).unwrap_err();
+    assert_eq!(
+        "SUBSCRIBE at offset 4: topic length 300 exceeds remaining 1",
+        format!("{}", err)
+    );
+}
+
 #[test]
 fn test_half_connect() {
     let mut data: &[u8] = &[
@@ -169,6 +405,28 @@ fn test_connect_wrong_version() {
     assert!(decode_slice(&mut data).is_err(), "Unknown version should return error");
 }
 
+#[test]
+fn connect_unknown_protocol() {
+    // MQTT 5 connect, with properties that MQTT 3.1.1 can't parse
+    let data = [
+        0b00010000, 16, 0, 4, b'M', b'Q', b'T', b'T', 5, 0b10, 0, 30, 2, 0x21, 0, 0, 1, b'a',
+    ];
+    let err = decode_slice(&data).unwrap_err();
+    assert_eq!(Error::InvalidProtocol("MQTT".into(), 5), err);
+    assert_eq!(
+        Some(ConnectReturnCode::RefusedProtocolVersion),
+        err.connect_return_code()
+    );
+    let data = [
+        0b00010000, 14, 0, 11, b'M', b'Q', b'T', b'T', b'-', b'v', b'e', b'r', b'y', b'-', b'l', 4,
+    ];
+    let err = decode_slice(&data).unwrap_err();
+    assert_eq!(Error::InvalidProtocol("MQTT-very-l".into(), 4), err);
+    // Not MQTT: no connack required
+    assert_eq!(None, err.connect_return_code());
+    assert_eq!(None, Error::InvalidPid.connect_return_code());
+}
+
 #[test]
 fn test_connect() {
     let mut data: &[u8] = &[
@@ -281,7 +539,7 @@ fn test_publish() {
     let mut offset = 0;
     assert_eq!(
         decoder::read_header(&data, &mut offset).unwrap(),
-        Some((decoder::Header::new(0b00110000).unwrap(), 10))
+        Some((decoder::FixedHeader::from_byte(0b00110000).unwrap(), 10))
     );
     assert_eq!(data.len(), 38);
 
@@ -0,0 +1,483 @@
+use crate::{decoder::*, utils::check_str, *};
+use core::{convert::TryFrom, ops::Range};
+use std::vec::Vec;
+
+/// Result of [dissect()]: the fields found in a packet, and the error that stopped decoding, if
+/// any.
+///
+/// [dissect()]: fn.dissect.html
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dissection<'a> {
+    /// Top-level fields, in buffer order.
+    pub fields: Vec<Field<'a>>,
+    /// Why dissection stopped early. Fields up to that point are still available.
+    pub error: Option<Error>,
+}
+
+/// One field of a dissected packet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field<'a> {
+    /// Field name, in `snake_case`.
+    pub name: &'static str,
+    /// Byte range of the field within the buffer given to [dissect()].
+    ///
+    /// [dissect()]: fn.dissect.html
+    pub range: Range<usize>,
+    /// Decoded value.
+    pub value: FieldValue<'a>,
+    /// Sub-fields, like the individual bits of a flags byte.
+    pub children: Vec<Field<'a>>,
+}
+
+/// Decoded value of a [Field].
+///
+/// [Field]: struct.Field.html
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue<'a> {
+    /// Structural field, see its children.
+    None,
+    Bool(bool),
+    Number(u32),
+    Str(&'a str),
+    Bytes(&'a [u8]),
+    PacketType(PacketType),
+    QoS(QoS),
+    Pid(Pid),
+    Protocol(Protocol),
+    ConnectReturnCode(ConnectReturnCode),
+    SubscribeReturnCode(SubscribeReturnCodes),
+}
+
+impl<'a> Field<'a> {
+    fn new(name: &'static str, range: Range<usize>, value: FieldValue<'a>) -> Self {
+        Field {
+            name,
+            range,
+            value,
+            children: Vec::new(),
+        }
+    }
+}
+
+/// Decode the first packet in `buf` into a tree of fields with their byte ranges.
+///
+/// This is a debugging tool, to find out which bytes of a frame correspond to which fields. Unlike
+/// [decode_slice()], it doesn't stop at the first problem without telling you anything: all the
+/// fields decoded before the error are returned. A truncated buffer yields
+/// `Error::UnexpectedEof`.
+///
+/// ```
+/// # use mqttrs::*;
+/// let buf = [0b01000000, 2, 0, 10];
+/// let d = dissect(&buf);
+/// assert_eq!(d.error, None);
+/// let names: Vec<_> = d.fields.iter().map(|f| (f.name, f.range.clone())).collect();
+/// assert_eq!(names, [("fixed_header", 0..2), ("pid", 2..4)]);
+///
+/// // Invalid pid: the fixed header is still dissected.
+/// let d = dissect(&[0b01000000, 2, 0, 0]);
+/// assert_eq!(d.error, Some(Error::InvalidPid));
+/// assert_eq!(d.fields.len(), 1);
+/// ```
+///
+/// [decode_slice()]: fn.decode_slice.html
+pub fn dissect(buf: &[u8]) -> Dissection<'_> {
+    let mut fields = Vec::new();
+    let error = dissect_packet(buf, &mut fields).err();
+    Dissection { fields, error }
+}
+
+fn dissect_packet<'a>(buf: &'a [u8], fields: &mut Vec<Field<'a>>) -> Result<(), Error> {
+    // Fixed header
+    let first = *buf.first().ok_or(Error::UnexpectedEof)?;
+    let mut fixed = Field::new("fixed_header", 0..1, FieldValue::None);
+    let header = FixedHeader::from_byte(first);
+    fixed.children.push(Field::new(
+        "packet_type",
+        0..1,
+        match header {
+            Ok(h) => FieldValue::PacketType(h.typ),
+            Err(_) => FieldValue::Number((first >> 4) as u32),
+        },
+    ));
+    let mut flags = Field::new("flags", 0..1, FieldValue::Number((first & 0b1111) as u32));
+    if let Ok(FixedHeader {
+        typ: PacketType::Publish,
+        dup,
+        qos,
+        retain,
+    }) = header
+    {
+        flags
+            .children
+            .push(Field::new("dup", 0..1, FieldValue::Bool(dup)));
+        flags
+            .children
+            .push(Field::new("qos", 0..1, FieldValue::QoS(qos)));
+        flags
+            .children
+            .push(Field::new("retain", 0..1, FieldValue::Bool(retain)));
+    }
+    fixed.children.push(flags);
+    let length = read_length(buf, 0);
+    if let Ok(Some((len, len_len))) = length {
+        fixed.range = 0..1 + len_len;
+        fixed.children.push(Field::new(
+            "remaining_length",
+            1..1 + len_len,
+            FieldValue::Number(len as u32),
+        ));
+    }
+    fields.push(fixed);
+    let header = header?;
+    let (len, len_len) = length?.ok_or(Error::UnexpectedEof)?;
+
+    // Variable header and payload
+    let mut r = Reader {
+        buf,
+        offset: 1 + len_len,
+        end: 1 + len_len + len,
+        packet: header.typ,
+    };
+    match header.typ {
+        PacketType::Connect => dissect_connect(&mut r, fields)?,
+        PacketType::Connack => {
+            let start = r.offset;
+            let f = r.u8("connack_flags")?;
+            let mut flags = Field::new(
+                "connack_flags",
+                start..r.offset,
+                FieldValue::Number(f as u32),
+            );
+            flags.children.push(Field::new(
+                "session_present",
+                start..r.offset,
+                FieldValue::Bool(f & 1 == 1),
+            ));
+            fields.push(flags);
+            let start = r.offset;
+            let code = ConnectReturnCode::from_u8(r.u8("return_code")?)?;
+            fields.push(Field::new(
+                "return_code",
+                start..r.offset,
+                FieldValue::ConnectReturnCode(code),
+            ));
+        }
+        PacketType::Publish => {
+            let (range, topic) = r.str("topic")?;
+            fields.push(Field::new("topic", range, FieldValue::Str(topic)));
+            if header.qos != QoS::AtMostOnce {
+                r.pid(fields)?;
+            }
+            let start = r.offset;
+            let payload = r.take(r.end - r.offset, "payload")?;
+            fields.push(Field::new(
+                "payload",
+                start..r.offset,
+                FieldValue::Bytes(payload),
+            ));
+        }
+        PacketType::Puback
+        | PacketType::Pubrec
+        | PacketType::Pubrel
+        | PacketType::Pubcomp
+        | PacketType::Unsuback => r.pid(fields)?,
+        PacketType::Subscribe => {
+            r.pid(fields)?;
+            while r.offset < r.end {
+                let (range, topic) = r.str("topic")?;
+                let mut sub = Field::new("subscription", range.clone(), FieldValue::None);
+                sub.children
+                    .push(Field::new("topic", range.clone(), FieldValue::Str(topic)));
+                let qos = QoS::from_u8(r.u8("qos")?)?;
+                sub.children
+                    .push(Field::new("qos", range.end..r.offset, FieldValue::QoS(qos)));
+                sub.range = range.start..r.offset;
+                fields.push(sub);
+            }
+        }
+        PacketType::Suback => {
+            r.pid(fields)?;
+            while r.offset < r.end {
+                let start = r.offset;
+                let code = match r.u8("return_code")? {
+                    0x80 => SubscribeReturnCodes::Failure,
+                    q => SubscribeReturnCodes::Success(QoS::from_u8(q)?),
+                };
+                fields.push(Field::new(
+                    "return_code",
+                    start..r.offset,
+                    FieldValue::SubscribeReturnCode(code),
+                ));
+            }
+        }
+        PacketType::Unsubscribe => {
+            r.pid(fields)?;
+            while r.offset < r.end {
+                let (range, topic) = r.str("topic")?;
+                fields.push(Field::new("topic", range, FieldValue::Str(topic)));
+            }
+        }
+        PacketType::Pingreq | PacketType::Pingresp | PacketType::Disconnect => (),
+    }
+    Ok(())
+}
+
+fn dissect_connect<'a>(r: &mut Reader<'a>, fields: &mut Vec<Field<'a>>) -> Result<(), Error> {
+    let (range, name) = r.str("protocol_name")?;
+    fields.push(Field::new(
+        "protocol_name",
+        range.clone(),
+        FieldValue::Str(name),
+    ));
+    let start = r.offset;
+    let level = r.u8("protocol_level")?;
+    fields.push(Field::new(
+        "protocol_level",
+        start..r.offset,
+        FieldValue::Number(level as u32),
+    ));
+    let protocol = Protocol::new(name, level)?;
+    fields.push(Field::new(
+        "protocol",
+        range.start..r.offset,
+        FieldValue::Protocol(protocol),
+    ));
+
+    let start = r.offset;
+    let f = r.u8("connect_flags")?;
+    let range = start..r.offset;
+    let mut flags = Field::new("connect_flags", range.clone(), FieldValue::Number(f as u32));
+    for &(name, bit) in &[
+        ("username", 0b1000_0000),
+        ("password", 0b0100_0000),
+        ("will_retain", 0b0010_0000),
+        ("will", 0b0000_0100),
+        ("clean_session", 0b0000_0010),
+    ] {
+        flags.children.push(Field::new(
+            name,
+            range.clone(),
+            FieldValue::Bool(f & bit != 0),
+        ));
+    }
+    let will_qos = QoS::from_u8((f & 0b11000) >> 3);
+    if let Ok(qos) = will_qos {
+        flags
+            .children
+            .push(Field::new("will_qos", range, FieldValue::QoS(qos)));
+    }
+    fields.push(flags);
+
+    let start = r.offset;
+    let keep_alive = r.u16("keep_alive")?;
+    fields.push(Field::new(
+        "keep_alive",
+        start..r.offset,
+        FieldValue::Number(keep_alive as u32),
+    ));
+    let (range, client_id) = r.str("client_id")?;
+    fields.push(Field::new("client_id", range, FieldValue::Str(client_id)));
+    if f & 0b100 != 0 {
+        will_qos?;
+        let (range, topic) = r.str("will_topic")?;
+        fields.push(Field::new("will_topic", range, FieldValue::Str(topic)));
+        let (range, message) = r.bytes("will_message")?;
+        fields.push(Field::new(
+            "will_message",
+            range,
+            FieldValue::Bytes(message),
+        ));
+    }
+    if f & 0b1000_0000 != 0 {
+        let (range, username) = r.str("username")?;
+        fields.push(Field::new("username", range, FieldValue::Str(username)));
+    }
+    if f & 0b0100_0000 != 0 {
+        let (range, password) = r.bytes("password")?;
+        fields.push(Field::new("password", range, FieldValue::Bytes(password)));
+    }
+    Ok(())
+}
+
+/// Bounds-checked reads within one packet.
+struct Reader<'a> {
+    buf: &'a [u8],
+    offset: usize,
+    end: usize,
+    packet: PacketType,
+}
+
+impl<'a> Reader<'a> {
+    /// Take `len` bytes of a field starting at `start`, which may be before the current offset.
+    fn take_from(
+        &mut self,
+        start: usize,
+        len: usize,
+        field: &'static str,
+    ) -> Result<&'a [u8], Error> {
+        if self.offset + len > self.end {
+            Err(Error::FieldTooLong {
+                packet: self.packet,
+                offset: start,
+                field,
+                len,
+                remaining: self.end - self.offset,
+            })
+        } else if self.offset + len > self.buf.len() {
+            Err(Error::UnexpectedEof)
+        } else {
+            let bytes = &self.buf[self.offset..self.offset + len];
+            self.offset += len;
+            Ok(bytes)
+        }
+    }
+
+    fn take(&mut self, len: usize, field: &'static str) -> Result<&'a [u8], Error> {
+        self.take_from(self.offset, len, field)
+    }
+
+    fn u8(&mut self, field: &'static str) -> Result<u8, Error> {
+        Ok(self.take(1, field)?[0])
+    }
+
+    fn u16(&mut self, field: &'static str) -> Result<u16, Error> {
+        let b = self.take(2, field)?;
+        Ok(((b[0] as u16) << 8) | b[1] as u16)
+    }
+
+    fn bytes(&mut self, field: &'static str) -> Result<(Range<usize>, &'a [u8]), Error> {
+        let start = self.offset;
+        let len = self.u16(field)? as usize;
+        let bytes = self.take_from(start, len, field)?;
+        Ok((start..self.offset, bytes))
+    }
+
+    fn str(&mut self, field: &'static str) -> Result<(Range<usize>, &'a str), Error> {
+        let (range, bytes) = self.bytes(field)?;
+        let s = core::str::from_utf8(bytes).map_err(Error::InvalidString)?;
+        check_str(s)?;
+        Ok((range, s))
+    }
+
+    fn pid(&mut self, fields: &mut Vec<Field<'a>>) -> Result<(), Error> {
+        let start = self.offset;
+        let pid = Pid::try_from(self.u16("pid")?)?;
+        fields.push(Field::new("pid", start..self.offset, FieldValue::Pid(pid)));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    fn names<'a>(fields: &[Field<'a>]) -> Vec<(&'static str, core::ops::Range<usize>)> {
+        fields.iter().map(|f| (f.name, f.range.clone())).collect()
+    }
+
+    #[test]
+    fn connect() {
+        let data: &[u8] = &[
+            0b00010000, 31, 0x00, 0x04, b'M', b'Q', b'T', b'T', 0x04,
+            0b11000110, // +username, +password, -will retain, will qos=0, +last_will, +clean_session
+            0x00, 0x0a, // 10 sec
+            0x00, 0x01, b'c', // client_id
+            0x00, 0x02, b'/', b'a', // will topic = '/a'
+            0x00, 0x01, b'x', // will msg = 'x'
+            0x00, 0x04, b'r', b'u', b's', b't', // username = 'rust'
+            0x00, 0x02, b'm', b'q', // password = 'mq'
+        ];
+        let d = dissect(data);
+        assert_eq!(d.error, None);
+        assert_eq!(
+            names(&d.fields),
+            vec![
+                ("fixed_header", 0..2),
+                ("protocol_name", 2..8),
+                ("protocol_level", 8..9),
+                ("protocol", 2..9),
+                ("connect_flags", 9..10),
+                ("keep_alive", 10..12),
+                ("client_id", 12..15),
+                ("will_topic", 15..19),
+                ("will_message", 19..22),
+                ("username", 22..28),
+                ("password", 28..32),
+            ]
+        );
+        assert_eq!(
+            names(&d.fields[0].children),
+            vec![
+                ("packet_type", 0..1),
+                ("flags", 0..1),
+                ("remaining_length", 1..2)
+            ]
+        );
+        assert_eq!(
+            d.fields[0].children[0].value,
+            FieldValue::PacketType(PacketType::Connect)
+        );
+        assert_eq!(d.fields[6].value, FieldValue::Str("c"));
+        assert_eq!(d.fields[10].value, FieldValue::Bytes(b"mq"));
+        let will = d.fields[4]
+            .children
+            .iter()
+            .find(|f| f.name == "will")
+            .unwrap();
+        assert_eq!(will.value, FieldValue::Bool(true));
+    }
+
+    #[test]
+    fn publish_truncated() {
+        let data: &[u8] = &[
+            0b00110010, 12, 0x00, 0x03, b'a', b'/', b'b', 0, 10, b'h', b'e', b'l',
+        ];
+        let d = dissect(data);
+        assert_eq!(d.error, Some(Error::UnexpectedEof));
+        assert_eq!(
+            names(&d.fields),
+            vec![("fixed_header", 0..2), ("topic", 2..7), ("pid", 7..9)]
+        );
+        let flags = &d.fields[0].children[1];
+        assert_eq!(flags.children[1].value, FieldValue::QoS(QoS::AtLeastOnce));
+    }
+
+    #[test]
+    fn malformed() {
+        // Second subscription has qos 3
+        let data: &[u8] = &[0b10000010, 10, 0, 10, 0, 1, b'a', 1, 0, 1, b'b', 3];
+        let d = dissect(data);
+        assert_eq!(d.error, Some(Error::InvalidQos(3)));
+        assert_eq!(
+            names(&d.fields),
+            vec![
+                ("fixed_header", 0..2),
+                ("pid", 2..4),
+                ("subscription", 4..8)
+            ]
+        );
+
+        // Topic length longer than the packet
+        let data: &[u8] = &[0b00110000, 4, 0, 9, b'a', b'b', 0xff, 0xff];
+        let d = dissect(data);
+        assert_eq!(
+            d.error,
+            Some(Error::FieldTooLong {
+                packet: PacketType::Publish,
+                offset: 2,
+                field: "topic",
+                len: 9,
+                remaining: 2
+            })
+        );
+        assert_eq!(d.error, decode_slice(data).err());
+        assert_eq!(names(&d.fields), vec![("fixed_header", 0..2)]);
+
+        // Reserved flags set
+        let d = dissect(&[0b01000001, 2, 0, 10]);
+        assert_eq!(d.error, Some(Error::InvalidFlags(PacketType::Puback, 1)));
+        assert_eq!(d.fields[0].children[0].value, FieldValue::Number(4));
+        assert_eq!(d.fields[0].children[1].value, FieldValue::Number(1));
+    }
+}
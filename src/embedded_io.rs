@@ -0,0 +1,242 @@
+//! Packet read/write functions over [embedded-io] transports.
+//!
+//! These are meant for `no_std` targets talking to UARTs, modems, network chips, etc. All the
+//! buffers are provided by the caller. The async variants require the `embedded-io-async` feature.
+//!
+//! [embedded-io]: https://docs.rs/embedded-io
+
+use crate::{
+    decoder::{check_packet_size, read_length},
+    *,
+};
+use ::embedded_io::{ErrorKind, ReadExactError};
+
+/// Read exactly one [Packet] from an [embedded_io::Read] source into `buf`.
+///
+/// Returns `Error::PacketTooLarge` as soon as the fixed header shows that the packet doesn't fit in
+/// `buf`, so the buffer size is also the maximum packet size.
+///
+/// ```
+/// # use mqttrs::*;
+/// let mut uart: &[u8] = &[0b11010000, 0];
+/// let mut buf = [0u8; 64];
+/// assert_eq!(Ok(Packet::Pingresp), embedded_io::read_packet(&mut uart, &mut buf));
+/// ```
+///
+/// [Packet]: ../enum.Packet.html
+/// [embedded_io::Read]: https://docs.rs/embedded-io/0.6/embedded_io/trait.Read.html
+pub fn read_packet<'a, R: ::embedded_io::Read>(
+    reader: &mut R,
+    buf: &'a mut [u8],
+) -> Result<Packet<'a>, Error> {
+    // Header byte and remaining length
+    let mut header_len = 0;
+    let end = loop {
+        if header_len == buf.len() {
+            // The packet is at least one byte bigger than the buffer
+            return Err(Error::PacketTooLarge {
+                size: header_len + 1,
+                max: buf.len(),
+            });
+        }
+        reader
+            .read_exact(&mut buf[header_len..header_len + 1])
+            .map_err(read_error)?;
+        header_len += 1;
+        if let Some(end) = packet_end(buf, header_len)? {
+            break end;
+        }
+    };
+
+    // Rest of the packet
+    reader
+        .read_exact(&mut buf[header_len..end])
+        .map_err(read_error)?;
+
+    let buf: &'a [u8] = buf;
+    decode_slice(&buf[..end])?.ok_or(Error::InvalidLength)
+}
+
+/// Encode a [Packet] into `buf` and write it to an [embedded_io::Write] sink.
+///
+/// Returns the number of bytes written.
+///
+/// [Packet]: ../enum.Packet.html
+/// [embedded_io::Write]: https://docs.rs/embedded-io/0.6/embedded_io/trait.Write.html
+pub fn write_packet<W: ::embedded_io::Write>(
+    writer: &mut W,
+    packet: &Packet,
+    buf: &mut [u8],
+) -> Result<usize, Error> {
+    let len = encode_slice(packet, buf)?;
+    writer.write_all(&buf[..len]).map_err(io_error)?;
+    Ok(len)
+}
+
+/// Async version of [read_packet()], over an [embedded_io_async::Read] source.
+///
+/// [read_packet()]: fn.read_packet.html
+/// [embedded_io_async::Read]: https://docs.rs/embedded-io-async/0.6/embedded_io_async/trait.Read.html
+#[cfg(feature = "embedded-io-async")]
+pub async fn read_packet_async<'a, R: ::embedded_io_async::Read>(
+    reader: &mut R,
+    buf: &'a mut [u8],
+) -> Result<Packet<'a>, Error> {
+    // Header byte and remaining length
+    let mut header_len = 0;
+    let end = loop {
+        if header_len == buf.len() {
+            // The packet is at least one byte bigger than the buffer
+            return Err(Error::PacketTooLarge {
+                size: header_len + 1,
+                max: buf.len(),
+            });
+        }
+        reader
+            .read_exact(&mut buf[header_len..header_len + 1])
+            .await
+            .map_err(read_error)?;
+        header_len += 1;
+        if let Some(end) = packet_end(buf, header_len)? {
+            break end;
+        }
+    };
+
+    // Rest of the packet
+    reader
+        .read_exact(&mut buf[header_len..end])
+        .await
+        .map_err(read_error)?;
+
+    let buf: &'a [u8] = buf;
+    decode_slice(&buf[..end])?.ok_or(Error::InvalidLength)
+}
+
+/// Async version of [write_packet()], over an [embedded_io_async::Write] sink.
+///
+/// [write_packet()]: fn.write_packet.html
+/// [embedded_io_async::Write]: https://docs.rs/embedded-io-async/0.6/embedded_io_async/trait.Write.html
+#[cfg(feature = "embedded-io-async")]
+pub async fn write_packet_async<W: ::embedded_io_async::Write>(
+    writer: &mut W,
+    packet: &Packet<'_>,
+    buf: &mut [u8],
+) -> Result<usize, Error> {
+    let len = encode_slice(packet, buf)?;
+    writer.write_all(&buf[..len]).await.map_err(io_error)?;
+    Ok(len)
+}
+
+/// Given the first `header_len` bytes of `buf`, return the total packet length once the remaining
+/// length is complete, making sure that the packet fits in `buf`.
+fn packet_end(buf: &[u8], header_len: usize) -> Result<Option<usize>, Error> {
+    match read_length(&buf[..header_len], 0)? {
+        Some((len, _)) => {
+            check_packet_size(header_len + len, buf.len())?;
+            Ok(Some(header_len + len))
+        }
+        None => Ok(None),
+    }
+}
+
+fn io_error<E: ::embedded_io::Error>(err: E) -> Error {
+    match err.kind() {
+        ErrorKind::WriteZero => Error::WriteZero,
+        k => Error::EmbeddedIo(k),
+    }
+}
+
+fn read_error<E: ::embedded_io::Error>(err: ReadExactError<E>) -> Error {
+    match err {
+        ReadExactError::UnexpectedEof => Error::UnexpectedEof,
+        ReadExactError::Other(e) => io_error(e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+    use core::convert::TryFrom;
+
+    #[test]
+    fn roundtrip() {
+        let packets: [Packet; 3] = [
+            Packet::Puback(Pid::try_from(19).unwrap()),
+            Publish {
+                dup: false,
+                qospid: QosPid::AtMostOnce,
+                retain: false,
+                topic_name: "a/b",
+                payload: b"hello",
+            }
+            .into(),
+            Packet::Disconnect,
+        ];
+
+        let mut stream = [0u8; 64];
+        let mut writer = &mut stream[..];
+        let mut scratch = [0u8; 16];
+        let mut written = 0;
+        for pkt in &packets {
+            written += embedded_io::write_packet(&mut writer, pkt, &mut scratch).unwrap();
+        }
+        assert_eq!(written, 4 + 12 + 2);
+
+        let mut reader = &stream[..written];
+        let mut buf = [0u8; 16];
+        for pkt in &packets {
+            assert_eq!(
+                Ok(pkt),
+                embedded_io::read_packet(&mut reader, &mut buf).as_ref()
+            );
+        }
+        assert_eq!(
+            Err(Error::UnexpectedEof),
+            embedded_io::read_packet(&mut reader, &mut buf)
+        );
+    }
+
+    #[test]
+    fn small_buffers() {
+        let mut reader: &[u8] = &[0b01000000, 2, 0, 10];
+        let mut buf = [0u8; 3];
+        assert_eq!(
+            Err(Error::PacketTooLarge { size: 4, max: 3 }),
+            embedded_io::read_packet(&mut reader, &mut buf)
+        );
+
+        let mut stream = [0u8; 3];
+        let mut writer = &mut stream[..];
+        let mut scratch = [0u8; 16];
+        let pkt = Packet::Puback(Pid::try_from(10).unwrap());
+        assert_eq!(
+            Err(Error::WriteZero),
+            embedded_io::write_packet(&mut writer, &pkt, &mut scratch)
+        );
+    }
+
+    #[cfg(feature = "embedded-io-async")]
+    #[test]
+    fn roundtrip_async() {
+        use futures::executor::block_on;
+
+        let pkt = Packet::Pubrec(Pid::try_from(12).unwrap());
+        let mut stream = [0u8; 8];
+        let mut writer = &mut stream[..];
+        let mut scratch = [0u8; 8];
+        let written = block_on(embedded_io::write_packet_async(
+            &mut writer,
+            &pkt,
+            &mut scratch,
+        ))
+        .unwrap();
+        assert_eq!(written, 4);
+
+        let mut reader = &stream[..written];
+        let mut buf = [0u8; 8];
+        assert_eq!(
+            Ok(pkt),
+            block_on(embedded_io::read_packet_async(&mut reader, &mut buf))
+        );
+    }
+}
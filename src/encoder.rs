@@ -1,4 +1,13 @@
-use crate::{Error, Packet};
+use crate::{
+    decoder::{check_field, read_bytes, Input},
+    peek_header,
+    utils::check_str,
+    Error, FixedHeader, Packet, PacketType, Pid, Publish, QoS,
+};
+#[cfg(feature = "std")]
+use bytes::BufMut;
+#[cfg(feature = "std")]
+use std::vec;
 
 /// Encode a [Packet] enum into a [BufMut] buffer.
 ///
@@ -33,6 +42,12 @@ use crate::{Error, Packet};
 // }
 
 pub fn encode_slice(packet: &Packet, buf: &mut [u8]) -> Result<usize, Error> {
+    check_capacity(buf, packet_len(packet)?)?;
+    write_packet(packet, buf)
+}
+
+/// Write a packet whose length has been checked by `packet_len()`.
+fn write_packet(packet: &Packet, buf: &mut [u8]) -> Result<usize, Error> {
     let mut offset = 0;
 
     match packet {
@@ -114,6 +129,267 @@ pub fn encode_slice(packet: &Packet, buf: &mut [u8]) -> Result<usize, Error> {
     }
 }
 
+/// Encode as many whole packets as fit into `buf`, one after the other, for example to send them
+/// with a single write.
+///
+/// Returns the number of packets written and their total length. It stops before the first packet
+/// that doesn't fit, so that it can be retried first in the next call. `Error::BufferTooSmall` is
+/// only returned if the first packet doesn't fit. All the packets are checked before anything is
+/// written: if any of them fails to encode, its error is returned and `buf` is left untouched.
+///
+/// ```
+/// # use mqttrs::*;
+/// let acks = [Packet::Puback(Pid::new()), Packet::Puback(Pid::new() + 1), Packet::Pingreq];
+/// let mut buf = [0u8; 9];
+/// assert_eq!(Ok((2, 8)), encode_many(&acks, &mut buf));
+/// assert_eq!(Ok((1, 2)), encode_many(&acks[2..], &mut buf));
+/// assert_eq!(
+///     Err(Error::BufferTooSmall { required: 4, available: 2 }),
+///     encode_many(&acks, &mut buf[..2])
+/// );
+/// ```
+pub fn encode_many(packets: &[Packet], buf: &mut [u8]) -> Result<(usize, usize), Error> {
+    let (count, len) = many_len(packets, buf.len())?;
+    write_many(&packets[..count], &mut buf[..len])?;
+    Ok((count, len))
+}
+
+/// Like [encode_many()], writing into a [BufMut] up to its `remaining_mut()` capacity.
+///
+/// `BufMut`'s free space may be uninitialized or split in chunks, so the packets are encoded into a
+/// scratch `Vec`, and then copied into `buf`.
+///
+/// ```
+/// # use mqttrs::*;
+/// # use bytes::BytesMut;
+/// let acks = [Packet::Puback(Pid::new()), Packet::Pingreq];
+/// let mut buf = BytesMut::new();
+/// assert_eq!(Ok((2, 6)), encode_many_buf(&acks, &mut buf));
+/// assert_eq!(&buf[..], &[0b01000000, 2, 0, 1, 0b11000000, 0]);
+/// ```
+///
+/// [encode_many()]: fn.encode_many.html
+/// [BufMut]: https://docs.rs/bytes/0.5.6/bytes/trait.BufMut.html
+#[cfg(feature = "std")]
+pub fn encode_many_buf(packets: &[Packet], buf: &mut impl BufMut) -> Result<(usize, usize), Error> {
+    let (count, len) = many_len(packets, buf.remaining_mut())?;
+    let mut scratch = vec![0; len];
+    write_many(&packets[..count], &mut scratch)?;
+    buf.put_slice(&scratch);
+    Ok((count, len))
+}
+
+/// Check all the `packets`, and count how many of them fit in `available` bytes, stopping at the
+/// first one that doesn't. Returns that count and their total length.
+fn many_len(packets: &[Packet], available: usize) -> Result<(usize, usize), Error> {
+    let (mut count, mut len) = (0, 0);
+    let mut full = false;
+    for packet in packets {
+        let required = packet_len(packet)?;
+        if !full && len + required <= available {
+            count += 1;
+            len += required;
+        } else if count == 0 {
+            return Err(Error::BufferTooSmall {
+                required,
+                available,
+            });
+        } else {
+            full = true;
+        }
+    }
+    Ok((count, len))
+}
+
+/// Write packets that have been checked by `many_len()`, into a buffer of their total length.
+fn write_many(packets: &[Packet], buf: &mut [u8]) -> Result<(), Error> {
+    let mut offset = 0;
+    for packet in packets {
+        offset += write_packet(packet, &mut buf[offset..])?;
+    }
+    Ok(())
+}
+
+/// Like [encode_slice()], but first checks the packet with [Packet::validate()].
+///
+/// Fails with `Error::Violation` holding the first violation found, without writing anything.
+///
+/// ```
+/// # use mqttrs::*;
+/// let mut buf = [0u8; 64];
+/// let unsubscribe = Unsubscribe::new(Pid::new(), Vec::new()).into();
+/// assert_eq!(
+///     Err(Error::Violation(Violation::NoTopics(PacketType::Unsubscribe))),
+///     encode_slice_validated(&unsubscribe, &mut buf)
+/// );
+/// ```
+///
+/// [encode_slice()]: fn.encode_slice.html
+/// [Packet::validate()]: enum.Packet.html#method.validate
+pub fn encode_slice_validated(packet: &Packet, buf: &mut [u8]) -> Result<usize, Error> {
+    if let Err(violations) = packet.validate() {
+        return Err(Error::Violation(violations[0]));
+    }
+    encode_slice(packet, buf)
+}
+
+/// Encode the fixed header, topic and pid of a [Publish] packet, without its payload.
+///
+/// The returned length covers only the bytes written to `buf`. The complete packet is these bytes
+/// followed by `publish.payload`, which lets you send the payload straight from its own buffer
+/// (using vectored writes, DMA scatter lists, etc) instead of copying it.
+///
+/// ```
+/// # use mqttrs::*;
+/// let publish = Publish {
+///    dup: false,
+///    qospid: QosPid::AtMostOnce,
+///    retain: false,
+///    topic_name: "test",
+///    payload: b"hello",
+/// };
+///
+/// let mut header = [0u8; 16];
+/// let len = encode_publish_header(&publish, &mut header).expect("failed encoding");
+///
+/// let mut full = [0u8; 32];
+/// let full_len = encode_slice(&publish.clone().into(), &mut full).unwrap();
+/// assert_eq!(full_len, len + publish.payload.len());
+/// assert_eq!(&full[..len], &header[..len]);
+/// assert_eq!(&full[len..full_len], publish.payload);
+/// ```
+///
+/// [Publish]: struct.Publish.html
+pub fn encode_publish_header(publish: &Publish, buf: &mut [u8]) -> Result<usize, Error> {
+    check_capacity(buf, publish.header_len()?)?;
+    let mut offset = 0;
+    publish.header_to_buffer(buf, &mut offset)
+}
+
+/// Set or clear the dup flag of an encoded publish, typically before retransmitting it.
+///
+/// `frame` must hold the whole packet, which must be a QoS 1 or 2 publish. Fails with
+/// `Error::NotQosPublish` otherwise, without modifying `frame`.
+///
+/// ```
+/// # use mqttrs::*;
+/// let publish = Publish {
+///    dup: false,
+///    qospid: QosPid::AtLeastOnce(Pid::new()),
+///    retain: false,
+///    topic_name: "test",
+///    payload: b"hello",
+/// };
+/// let mut frame = [0u8; 32];
+/// let len = encode_slice(&publish.into(), &mut frame).unwrap();
+/// let frame = &mut frame[..len];
+/// set_publish_dup(frame, true).unwrap();
+/// set_publish_pid(frame, Pid::new() + 41).unwrap();
+/// match decode_slice(frame) {
+///     Ok(Some(Packet::Publish(p))) => {
+///         assert!(p.dup);
+///         assert_eq!(p.qospid, QosPid::AtLeastOnce(Pid::new() + 41));
+///     }
+///     other => panic!("unexpected {:?}", other),
+/// }
+/// ```
+pub fn set_publish_dup(frame: &mut [u8], dup: bool) -> Result<(), Error> {
+    publish_pid_offset(frame)?;
+    if dup {
+        frame[0] |= 0b1000;
+    } else {
+        frame[0] &= !0b1000;
+    }
+    Ok(())
+}
+
+/// Rewrite the pid of an encoded publish.
+///
+/// `frame` must hold the whole packet, which must be a QoS 1 or 2 publish. Fails with
+/// `Error::NotQosPublish` otherwise, without modifying `frame`.
+pub fn set_publish_pid(frame: &mut [u8], pid: Pid) -> Result<(), Error> {
+    let mut offset = publish_pid_offset(frame)?;
+    write_u16(frame, &mut offset, pid.get())
+}
+
+/// Check that `frame` holds a whole QoS 1 or 2 publish, and return the offset of its pid.
+fn publish_pid_offset(frame: &[u8]) -> Result<usize, Error> {
+    let (header, header_len, remaining_len) = peek_header(frame)?.ok_or(Error::UnexpectedEof)?;
+    let frame = frame
+        .get(..header_len + remaining_len)
+        .ok_or(Error::UnexpectedEof)?;
+    let frame = Input::new(frame);
+    if header.typ != PacketType::Publish || header.qos == QoS::AtMostOnce {
+        return Err(Error::NotQosPublish(header.typ));
+    }
+    let mut offset = header_len;
+    read_bytes(&frame, &mut offset, PacketType::Publish, "topic")?;
+    check_field(&frame, offset, 2, PacketType::Publish, "pid")?;
+    Ok(offset)
+}
+
+/// Encode a fixed header announcing `remaining_len` bytes, and return its length.
+///
+/// Fails with `Error::InvalidFlags` if the header's flags aren't valid for its packet type, and
+/// with `Error::InvalidLength` if `remaining_len` is above the maximum remaining length.
+///
+/// ```
+/// # use mqttrs::*;
+/// let mut buf = [0u8; 5];
+/// let header = FixedHeader::new(PacketType::Subscribe);
+/// assert_eq!(Ok(3), encode_fixed_header(&header, 200, &mut buf));
+/// assert_eq!(&buf[..3], &[0b10000010, 0xc8, 0x01]);
+/// assert_eq!(Ok(Some((header, 3, 200))), peek_header(&buf));
+/// ```
+pub fn encode_fixed_header(
+    header: &FixedHeader,
+    remaining_len: usize,
+    buf: &mut [u8],
+) -> Result<usize, Error> {
+    let byte = header.to_byte();
+    if FixedHeader::from_byte(byte)? != *header {
+        return Err(Error::InvalidFlags(header.typ, byte & 0b1111));
+    }
+    let len = 1 + length_size(remaining_len)?;
+    check_capacity(buf, len)?;
+    let mut offset = 0;
+    write_u8(buf, &mut offset, byte)?;
+    write_length_bytes(buf, &mut offset, remaining_len)?;
+    Ok(len)
+}
+
+/// Total encoded size of a packet. This also checks everything that could make encoding fail, so
+/// that nothing gets written to a buffer unless the whole packet can be.
+pub(crate) fn packet_len(packet: &Packet) -> Result<usize, Error> {
+    let len = match packet {
+        Packet::Connect(connect) => connect.remaining_len()?,
+        Packet::Publish(publish) => publish.remaining_len()?,
+        Packet::Subscribe(subscribe) => subscribe.remaining_len()?,
+        Packet::Suback(suback) => suback.remaining_len(),
+        Packet::Unsubscribe(unsubscribe) => unsubscribe.remaining_len()?,
+        Packet::Connack(_)
+        | Packet::Puback(_)
+        | Packet::Pubrec(_)
+        | Packet::Pubrel(_)
+        | Packet::Pubcomp(_)
+        | Packet::Unsuback(_) => 2,
+        Packet::Pingreq | Packet::Pingresp | Packet::Disconnect => 0,
+    };
+    Ok(1 + length_size(len)? + len)
+}
+
+/// Fail with `Error::BufferTooSmall` if `buf` can't hold `required` bytes.
+pub(crate) fn check_capacity(buf: &[u8], required: usize) -> Result<(), Error> {
+    if buf.len() < required {
+        Err(Error::BufferTooSmall {
+            required,
+            available: buf.len(),
+        })
+    } else {
+        Ok(())
+    }
+}
+
 /// Check wether buffer has `len` bytes of write capacity left. Use this to return a clean
 /// Result::Err instead of panicking.
 pub(crate) fn check_remaining(buf: &mut [u8], offset: &mut usize, len: usize) -> Result<(), Error> {
@@ -126,25 +402,29 @@ pub(crate) fn check_remaining(buf: &mut [u8], offset: &mut usize, len: usize) ->
 
 /// http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718023
 pub(crate) fn write_length(buf: &mut [u8], offset: &mut usize, len: usize) -> Result<usize, Error> {
-    let write_len = match len {
-        0..=127 => {
-            check_remaining(buf, offset, len + 1)?;
-            len + 1
-        }
-        128..=16383 => {
-            check_remaining(buf, offset, len + 2)?;
-            len + 2
-        }
-        16384..=2097151 => {
-            check_remaining(buf, offset, len + 3)?;
-            len + 3
-        }
-        2097152..=268435455 => {
-            check_remaining(buf, offset, len + 4)?;
-            len + 4
-        }
-        _ => return Err(Error::InvalidLength),
-    };
+    let write_len = len + length_size(len)?;
+    check_remaining(buf, offset, write_len)?;
+    write_length_bytes(buf, offset, len)?;
+    Ok(write_len)
+}
+
+/// Number of bytes needed to encode `len` as a remaining length.
+pub(crate) fn length_size(len: usize) -> Result<usize, Error> {
+    match len {
+        0..=127 => Ok(1),
+        128..=16383 => Ok(2),
+        16384..=2097151 => Ok(3),
+        2097152..=268435455 => Ok(4),
+        _ => Err(Error::InvalidLength),
+    }
+}
+
+/// Write the remaining length bytes, without checking for payload capacity.
+pub(crate) fn write_length_bytes(
+    buf: &mut [u8],
+    offset: &mut usize,
+    len: usize,
+) -> Result<(), Error> {
     let mut done = false;
     let mut x = len;
     while !done {
@@ -156,10 +436,9 @@ pub(crate) fn write_length(buf: &mut [u8], offset: &mut usize, len: usize) -> Re
         write_u8(buf, offset, byte)?;
         done = x <= 0;
     }
-    Ok(write_len)
+    Ok(())
 }
 
-
 pub(crate) fn write_u8(buf: &mut [u8], offset: &mut usize, val: u8) -> Result<(), Error> {
     buf[*offset] = val;
     *offset += 1;
@@ -171,7 +450,26 @@ pub(crate) fn write_u16(buf: &mut [u8], offset: &mut usize, val: u16) -> Result<
     write_u8(buf, offset, (val & 0xFF) as u8)
 }
 
+/// Encoded size of a length-prefixed binary field, failing if it's too long.
+pub(crate) fn bytes_len(bytes: &[u8]) -> Result<usize, Error> {
+    if bytes.len() > u16::MAX as usize {
+        return Err(Error::StringTooLong(bytes.len()));
+    }
+    Ok(2 + bytes.len())
+}
+
+/// Encoded size of a string, failing if it's too long or breaks the MQTT string rules.
+pub(crate) fn string_len(string: &str) -> Result<usize, Error> {
+    check_str(string)?;
+    bytes_len(string.as_bytes())
+}
+
+/// Write a length-prefixed binary field.
+///
+/// Callers also check it with `bytes_len()` before writing anything, so that encoding is
+/// all-or-nothing. This check is only a safety net.
 pub(crate) fn write_bytes(buf: &mut [u8], offset: &mut usize, bytes: &[u8]) -> Result<(), Error> {
+    bytes_len(bytes)?;
     write_u16(buf, offset, bytes.len() as u16)?;
 
     for &byte in bytes {
@@ -180,6 +478,12 @@ pub(crate) fn write_bytes(buf: &mut [u8], offset: &mut usize, bytes: &[u8]) -> R
     Ok(())
 }
 
+/// Write a length-prefixed string.
+///
+/// The public encoders already check the MQTT string rules with `string_len()` when sizing the
+/// packet. This second check keeps the `to_buffer()` methods from producing a packet that peers
+/// must reject, whoever calls them, and is cheap next to the copy.
 pub(crate) fn write_string(buf: &mut [u8], offset: &mut usize, string: &str) -> Result<(), Error> {
-    write_bytes(buf, offset, string.as_bytes(), )
+    check_str(string)?;
+    write_bytes(buf, offset, string.as_bytes())
 }
@@ -54,6 +54,26 @@ fn test_connect() {
     assert_decode_slice!(Packet::Connect(_), &packet, 18);
 }
 
+#[test]
+fn test_connect_mqisdp() {
+    let packet = Connect {
+        protocol: Protocol::MQIsdp,
+        keep_alive: 120,
+        client_id: "imvj",
+        clean_session: true,
+        last_will: None,
+        username: None,
+        password: None,
+    }
+    .into();
+    assert_decode_slice!(Packet::Connect(_), &packet, 20);
+
+    let mut buf = [0u8; 20];
+    encode_slice(&packet, &mut buf).unwrap();
+    assert_eq!(&buf[2..11], &[0, 6, b'M', b'Q', b'I', b's', b'd', b'p', 3]);
+    assert_eq!(Ok(Some(packet)), decode_slice(&buf));
+}
+
 #[test]
 fn test_write_zero() {
     let packet = Connect {
@@ -69,15 +89,188 @@ fn test_write_zero() {
 
     let mut slice = [0u8; 8];
     match encode_slice(&packet, &mut slice) {
-        Ok(_) => panic!("Expected Error::WriteZero, as input slice is too small"),
-        Err(e) => assert_eq!(e, Error::WriteZero),
+        Ok(_) => panic!("Expected Error::BufferTooSmall, as input slice is too small"),
+        Err(e) => assert_eq!(
+            e,
+            Error::BufferTooSmall {
+                required: 18,
+                available: 8
+            }
+        ),
     }
+    // Nothing was written
+    assert_eq!(slice, [0u8; 8]);
 
     let mut buf = [0u8; 80];
     let written = encode_slice(&packet, &mut buf).unwrap();
     assert_eq!(written, 18);
 }
 
+#[test]
+fn test_fixed_header() {
+    let mut buf = [0u8; 5];
+    for typ in &[
+        PacketType::Pubrel,
+        PacketType::Pingreq,
+        PacketType::Unsubscribe,
+    ] {
+        let pkt = match typ {
+            PacketType::Pubrel => Packet::Pubrel(Pid::new()),
+            PacketType::Pingreq => Packet::Pingreq,
+            _ => Unsubscribe::new(Pid::new(), vec![]).into(),
+        };
+        let len = encode_slice(&pkt, &mut buf).unwrap();
+        let header = FixedHeader::new(*typ);
+        assert_eq!(Ok(Some((header, 2, len - 2))), peek_header(&buf));
+        assert_eq!(Ok(2), encode_fixed_header(&header, len - 2, &mut buf));
+        assert_eq!(Ok(Some(pkt)), decode_slice(&buf[..len]));
+    }
+    let mut header = FixedHeader::new(PacketType::Publish);
+    header.qos = QoS::ExactlyOnce;
+    header.dup = true;
+    assert_eq!(Ok(5), encode_fixed_header(&header, 268_435_455, &mut buf));
+    assert_eq!(buf, [0b00111100, 0xff, 0xff, 0xff, 0x7f]);
+    assert_eq!(
+        Err(Error::InvalidLength),
+        encode_fixed_header(&header, 268_435_456, &mut buf)
+    );
+    assert_eq!(
+        Err(Error::BufferTooSmall {
+            required: 5,
+            available: 4
+        }),
+        encode_fixed_header(&header, 268_435_455, &mut buf[..4])
+    );
+    header.typ = PacketType::Subscribe;
+    assert_eq!(
+        Err(Error::InvalidFlags(PacketType::Subscribe, 0b1100)),
+        encode_fixed_header(&header, 0, &mut buf)
+    );
+}
+
+#[test]
+fn test_patch_publish() {
+    let pid = Pid::try_from(10).unwrap();
+    let mut publish = Publish {
+        dup: false,
+        qospid: QosPid::ExactlyOnce(pid),
+        retain: true,
+        topic_name: "a/b",
+        payload: b"hello",
+    };
+    let mut frame = [0u8; 32];
+    let len = encode_slice(&publish.clone().into(), &mut frame).unwrap();
+    let frame = &mut frame[..len];
+
+    set_publish_dup(frame, true).unwrap();
+    publish.dup = true;
+    assert_eq!(Ok(Some(publish.clone().into())), decode_slice(frame));
+    set_publish_pid(frame, pid + 1).unwrap();
+    publish.qospid = QosPid::ExactlyOnce(pid + 1);
+    assert_eq!(Ok(Some(publish.clone().into())), decode_slice(frame));
+    set_publish_dup(frame, false).unwrap();
+    publish.dup = false;
+    assert_eq!(Ok(Some(publish.into())), decode_slice(frame));
+
+    // Truncated or padded frames
+    assert_eq!(
+        Err(Error::UnexpectedEof),
+        set_publish_dup(&mut frame.to_vec()[..len - 1], true)
+    );
+    let mut padded = frame.to_vec();
+    padded.push(0xff);
+    set_publish_pid(&mut padded, pid).unwrap();
+    assert_eq!(0xff, padded[len]);
+
+    let mut qos0 = [0u8; 16];
+    let qos0_publish = Publish {
+        dup: false,
+        qospid: QosPid::AtMostOnce,
+        retain: false,
+        topic_name: "a/b",
+        payload: b"",
+    };
+    let len = encode_slice(&qos0_publish.into(), &mut qos0).unwrap();
+    assert_eq!(
+        Err(Error::NotQosPublish(PacketType::Publish)),
+        set_publish_dup(&mut qos0[..len], true)
+    );
+    let mut puback = [0b01000000, 2, 0, 10];
+    assert_eq!(
+        Err(Error::NotQosPublish(PacketType::Puback)),
+        set_publish_pid(&mut puback, pid)
+    );
+    assert_eq!(
+        "PUBACK is not a PUBLISH",
+        Error::NotQosPublish(PacketType::Puback).to_string()
+    );
+    // Pid past the remaining length
+    let mut short = [0b00110010, 5, 0, 3, b'a', b'/', b'b', 0, 10];
+    assert_eq!(
+        Err(Error::FieldTooLong {
+            packet: PacketType::Publish,
+            offset: 7,
+            field: "pid",
+            len: 2,
+            remaining: 0
+        }),
+        set_publish_pid(&mut short, pid)
+    );
+    assert_eq!([0, 10], short[7..]);
+}
+
+#[test]
+fn test_encode_many() {
+    let long = "a".repeat(65536);
+    let packets: Vec<Packet> = vec![
+        Packet::Pingreq,
+        Publish {
+            dup: false,
+            qospid: QosPid::AtMostOnce,
+            retain: false,
+            topic_name: &long,
+            payload: b"",
+        }
+        .into(),
+        Packet::Disconnect,
+    ];
+    let mut buf = [0u8; 16];
+    // Encoding errors aren't mistaken for a full buffer
+    assert_eq!(
+        Err(Error::StringTooLong(65536)),
+        encode_many(&packets, &mut buf)
+    );
+    assert_eq!(
+        Err(Error::StringTooLong(65536)),
+        encode_many_buf(&packets, &mut BytesMut::new())
+    );
+    // Nothing is written before the error
+    assert_eq!([0u8; 16], buf);
+    let mut vec = BytesMut::new();
+    assert!(encode_many_buf(&packets, &mut vec).is_err());
+    assert!(vec.is_empty());
+    assert_eq!(Ok((0, 0)), encode_many(&[], &mut buf));
+
+    let acks: Vec<Packet> = (1..=5).map(|n| Packet::Puback(Pid::new() + n)).collect();
+    let mut slice = &mut buf[..];
+    assert_eq!(Ok((4, 16)), encode_many_buf(&acks, &mut slice));
+    assert_eq!(0, slice.len());
+    assert_eq!(
+        Err(Error::BufferTooSmall {
+            required: 4,
+            available: 0
+        }),
+        encode_many_buf(&acks, &mut slice)
+    );
+    let (_, len) = encode_many(&acks, &mut buf).unwrap();
+    let mut iter = PacketIter::new(&buf[..len]);
+    assert!(acks[..4].iter().cloned().map(Ok).eq(&mut iter));
+    assert!(iter.remaining().is_empty());
+    let mut vec = BytesMut::new();
+    assert_eq!(Ok((5, 20)), encode_many_buf(&acks, &mut vec));
+    assert_eq!(&buf[..], &vec[..16]);
+}
+
 #[test]
 fn test_connack() {
     let packet = Connack {
@@ -187,3 +380,117 @@ fn test_disconnect() {
     // assert_decode!(Packet::Disconnect, &Packet::Disconnect);
     assert_decode_slice!(Packet::Disconnect, &Packet::Disconnect, 2);
 }
+
+#[test]
+fn test_publish_header() {
+    let publish = Publish {
+        dup: true,
+        qospid: QosPid::from_u8u16(1, 10),
+        retain: false,
+        topic_name: "asdf",
+        payload: &[0xAB; 200],
+    };
+    let mut full = [0u8; 512];
+    let full_len = encode_slice(&publish.clone().into(), &mut full).unwrap();
+
+    // Only needs room for the header, not the payload
+    let mut header = [0u8; 11];
+    let len = encode_publish_header(&publish, &mut header).unwrap();
+    assert_eq!(len, 11);
+    assert_eq!(full_len, len + 200);
+    assert_eq!(&full[..len], &header[..]);
+
+    let mut small = [0u8; 10];
+    assert_eq!(
+        Err(Error::BufferTooSmall {
+            required: 11,
+            available: 10
+        }),
+        encode_publish_header(&publish, &mut small)
+    );
+    assert_eq!(small, [0u8; 10]);
+}
+
+#[test]
+fn test_string_rules() {
+    let publish = |topic_name| -> Packet {
+        Publish {
+            dup: false,
+            qospid: QosPid::AtMostOnce,
+            retain: false,
+            topic_name,
+            payload: b"",
+        }
+        .into()
+    };
+    let mut buf = [0u8; 70_010];
+    assert_eq!(
+        Err(Error::InvalidChar('\0')),
+        encode_slice(&publish("a\0b"), &mut buf)
+    );
+    assert_eq!(
+        Err(Error::InvalidChar('\u{1b}')),
+        encode_slice(&publish("\u{1b}[31m"), &mut buf)
+    );
+    assert_eq!(
+        Err(Error::InvalidChar('\u{fffe}')),
+        encode_slice(&publish("a\u{fffe}"), &mut buf)
+    );
+    assert_eq!(Ok(9), encode_slice(&publish("été"), &mut buf));
+
+    let long = "a".repeat(70_000);
+    assert_eq!(
+        Err(Error::StringTooLong(70_000)),
+        encode_slice(&publish(&long), &mut buf)
+    );
+
+    // Every string field is checked, before anything gets written
+    let connect = |client_id, will_topic, username| -> Packet {
+        Connect {
+            protocol: Protocol::MQTT311,
+            keep_alive: 30,
+            client_id,
+            clean_session: true,
+            last_will: Some(LastWill {
+                topic: will_topic,
+                message: b"",
+                qos: QoS::AtMostOnce,
+                retain: false,
+            }),
+            username: Some(username),
+            password: None,
+        }
+        .into()
+    };
+    let pid = Pid::try_from(1).unwrap();
+    let bad = [
+        connect("a\0", "t", "u"),
+        connect("c", "a\0", "u"),
+        connect("c", "t", "a\0"),
+        Subscribe::new(
+            pid,
+            LimitedVec::from(vec![SubscribeTopic {
+                topic_path: "a\0".into(),
+                qos: QoS::AtMostOnce,
+            }]),
+        )
+        .into(),
+        Unsubscribe::new(pid, LimitedVec::from(vec!["a\0".into()])).into(),
+    ];
+    let mut small = [0u8; 64];
+    for pkt in &bad {
+        assert_eq!(Err(Error::InvalidChar('\0')), encode_slice(pkt, &mut small));
+        assert_eq!(small, [0u8; 64]);
+    }
+    if let Packet::Publish(p) = publish("a\0b") {
+        assert_eq!(
+            Err(Error::InvalidChar('\0')),
+            encode_publish_header(&p, &mut small)
+        );
+    }
+    // The writer checks too, should a caller skip the length computation
+    assert_eq!(
+        Err(Error::InvalidChar('\0')),
+        encoder::write_string(&mut small, &mut 0, "a\0")
+    );
+}
@@ -0,0 +1,155 @@
+//! Packet read/write functions over [futures-io] transports.
+//!
+//! `AsyncRead` and `AsyncWrite` from `futures-io` are implemented by (or easily adapted to) the
+//! streams of most async runtimes, like async-std, smol, or tokio via `tokio-util`'s `compat`.
+//!
+//! [futures-io]: https://docs.rs/futures-io
+
+use crate::{
+    decoder::{check_packet_size, read_length},
+    io::encode_vec,
+    *,
+};
+use ::futures_io::{AsyncRead, AsyncWrite};
+use core::{future::poll_fn, pin::Pin};
+use std::vec::Vec;
+
+/// Read exactly one [Packet] from an [AsyncRead] source.
+///
+/// `buf` is cleared and reused to hold the packet, which borrows from it.
+///
+/// ```
+/// # use mqttrs::*;
+/// # futures::executor::block_on(async {
+/// let mut stream: &[u8] = &[0b11000000, 0];
+/// let mut buf = Vec::new();
+/// let pkt = futures_io::read_packet(&mut stream, &mut buf).await;
+/// assert_eq!(Ok(Packet::Pingreq), pkt);
+/// # });
+/// ```
+///
+/// [Packet]: ../enum.Packet.html
+/// [AsyncRead]: https://docs.rs/futures-io/0.3/futures_io/trait.AsyncRead.html
+pub async fn read_packet<'a, R: AsyncRead + Unpin>(
+    reader: &mut R,
+    buf: &'a mut Vec<u8>,
+) -> Result<Packet<'a>, Error> {
+    read_packet_with_limit(reader, buf, usize::MAX).await
+}
+
+/// Like [read_packet()], but fails with `Error::PacketTooLarge` before reading or allocating the
+/// rest of a packet bigger than `max_packet_size` bytes (fixed header included).
+///
+/// [read_packet()]: fn.read_packet.html
+pub async fn read_packet_with_limit<'a, R: AsyncRead + Unpin>(
+    reader: &mut R,
+    buf: &'a mut Vec<u8>,
+    max_packet_size: usize,
+) -> Result<Packet<'a>, Error> {
+    buf.clear();
+
+    // Header byte and remaining length
+    let remaining_len = loop {
+        let mut byte = [0u8];
+        read_exact(reader, &mut byte).await?;
+        buf.push(byte[0]);
+        if let Some((len, _)) = read_length(buf, 0)? {
+            break len;
+        }
+    };
+
+    // Rest of the packet
+    let header_len = buf.len();
+    check_packet_size(header_len + remaining_len, max_packet_size)?;
+    buf.resize(header_len + remaining_len, 0);
+    read_exact(reader, &mut buf[header_len..]).await?;
+
+    decode_slice(buf)?.ok_or(Error::InvalidLength)
+}
+
+/// Encode a [Packet] and write it to an [AsyncWrite] sink.
+///
+/// Returns the number of bytes written. The sink is not flushed.
+///
+/// [Packet]: ../enum.Packet.html
+/// [AsyncWrite]: https://docs.rs/futures-io/0.3/futures_io/trait.AsyncWrite.html
+pub async fn write_packet<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    packet: &Packet<'_>,
+) -> Result<usize, Error> {
+    let mut buf = Vec::new();
+    let len = encode_vec(packet, &mut buf)?;
+    write_all(writer, &buf[..len]).await?;
+    Ok(len)
+}
+
+async fn read_exact<R: AsyncRead + Unpin>(reader: &mut R, mut buf: &mut [u8]) -> Result<(), Error> {
+    while !buf.is_empty() {
+        match poll_fn(|cx| Pin::new(&mut *reader).poll_read(cx, buf)).await? {
+            0 => return Err(Error::UnexpectedEof),
+            n => buf = &mut buf[n..],
+        }
+    }
+    Ok(())
+}
+
+async fn write_all<W: AsyncWrite + Unpin>(writer: &mut W, mut buf: &[u8]) -> Result<(), Error> {
+    while !buf.is_empty() {
+        match poll_fn(|cx| Pin::new(&mut *writer).poll_write(cx, buf)).await? {
+            0 => return Err(Error::WriteZero),
+            n => buf = &buf[n..],
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+    use core::convert::TryFrom;
+    use futures::executor::block_on;
+
+    #[test]
+    fn roundtrip() {
+        let payload = [7u8; 300];
+        let packets: Vec<Packet> = vec![
+            Packet::Puback(Pid::try_from(19).unwrap()),
+            Publish {
+                dup: false,
+                qospid: QosPid::ExactlyOnce(Pid::try_from(10).unwrap()),
+                retain: true,
+                topic_name: "a/b",
+                payload: &payload,
+            }
+            .into(),
+            Packet::Disconnect,
+        ];
+
+        let mut stream = Vec::new();
+        for pkt in &packets {
+            block_on(futures_io::write_packet(&mut stream, pkt)).unwrap();
+        }
+        assert_eq!(stream.len(), 4 + 310 + 2);
+
+        let mut reader = &stream[..];
+        let mut buf = Vec::new();
+        for pkt in &packets {
+            let res = block_on(futures_io::read_packet(&mut reader, &mut buf));
+            assert_eq!(Ok(pkt), res.as_ref());
+        }
+        assert_eq!(
+            Err(Error::UnexpectedEof),
+            block_on(futures_io::read_packet(&mut reader, &mut buf))
+        );
+    }
+
+    #[test]
+    fn truncated() {
+        let mut reader: &[u8] = &[0b01000000, 2, 0];
+        let mut buf = Vec::new();
+        assert_eq!(
+            Err(Error::UnexpectedEof),
+            block_on(futures_io::read_packet(&mut reader, &mut buf))
+        );
+    }
+}
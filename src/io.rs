@@ -0,0 +1,154 @@
+use crate::{
+    decoder::{check_packet_size, read_length},
+    *,
+};
+use std::{
+    io::{Read, Write},
+    vec::Vec,
+};
+
+/// Read exactly one [Packet] from a blocking [Read] source.
+///
+/// The header is read byte by byte until the remaining length is known, then the rest of the
+/// packet is read in one go. `buf` is cleared and reused to hold the packet, which borrows from it.
+///
+/// ```
+/// # use mqttrs::*;
+/// let mut stream: &[u8] = &[0b11000000, 0, 0b11010000, 0];
+/// let mut buf = Vec::new();
+/// assert_eq!(Ok(Packet::Pingreq), read_packet(&mut stream, &mut buf));
+/// assert_eq!(Ok(Packet::Pingresp), read_packet(&mut stream, &mut buf));
+/// ```
+///
+/// [Packet]: enum.Packet.html
+/// [Read]: https://doc.rust-lang.org/std/io/trait.Read.html
+pub fn read_packet<'a>(reader: &mut impl Read, buf: &'a mut Vec<u8>) -> Result<Packet<'a>, Error> {
+    read_packet_with_limit(reader, buf, usize::MAX)
+}
+
+/// Like [read_packet()], but fails with `Error::PacketTooLarge` before reading or allocating the
+/// rest of a packet bigger than `max_packet_size` bytes (fixed header included).
+///
+/// ```
+/// # use mqttrs::*;
+/// let mut stream: &[u8] = &[0b00110000, 0xe8, 0x07];
+/// let mut buf = Vec::new();
+/// assert_eq!(
+///     Err(Error::PacketTooLarge { size: 1003, max: 256 }),
+///     read_packet_with_limit(&mut stream, &mut buf, 256)
+/// );
+/// ```
+///
+/// [read_packet()]: fn.read_packet.html
+pub fn read_packet_with_limit<'a>(
+    reader: &mut impl Read,
+    buf: &'a mut Vec<u8>,
+    max_packet_size: usize,
+) -> Result<Packet<'a>, Error> {
+    buf.clear();
+
+    // Header byte and remaining length
+    let remaining_len = loop {
+        let mut byte = [0u8];
+        reader.read_exact(&mut byte)?;
+        buf.push(byte[0]);
+        if let Some((len, _)) = read_length(buf, 0)? {
+            break len;
+        }
+    };
+
+    // Rest of the packet
+    let header_len = buf.len();
+    check_packet_size(header_len + remaining_len, max_packet_size)?;
+    buf.resize(header_len + remaining_len, 0);
+    reader.read_exact(&mut buf[header_len..])?;
+
+    decode_slice(buf)?.ok_or(Error::InvalidLength)
+}
+
+/// Encode a [Packet] and write it to a blocking [Write] sink.
+///
+/// Returns the number of bytes written.
+///
+/// ```
+/// # use mqttrs::*;
+/// let mut stream = Vec::new();
+/// assert_eq!(Ok(2), write_packet(&mut stream, &Packet::Pingreq));
+/// assert_eq!(stream, [0b11000000, 0]);
+/// ```
+///
+/// [Packet]: enum.Packet.html
+/// [Write]: https://doc.rust-lang.org/std/io/trait.Write.html
+pub fn write_packet(writer: &mut impl Write, packet: &Packet) -> Result<usize, Error> {
+    let mut buf = Vec::new();
+    let len = encode_vec(packet, &mut buf)?;
+    writer.write_all(&buf[..len])?;
+    Ok(len)
+}
+
+/// Encode a packet into `buf`, growing it as needed. Returns the encoded length.
+pub(crate) fn encode_vec(packet: &Packet, buf: &mut Vec<u8>) -> Result<usize, Error> {
+    match encode_slice(packet, buf) {
+        Err(Error::BufferTooSmall { required, .. }) => {
+            buf.resize(required, 0);
+            encode_slice(packet, buf)
+        }
+        res => res,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+    use core::convert::TryFrom;
+    use std::io::{Cursor, ErrorKind};
+
+    #[test]
+    fn roundtrip() {
+        let payload = [7u8; 300];
+        let packets: Vec<Packet> = vec![
+            Packet::Puback(Pid::try_from(19).unwrap()),
+            Publish {
+                dup: false,
+                qospid: QosPid::AtLeastOnce(Pid::try_from(10).unwrap()),
+                retain: false,
+                topic_name: "a/b",
+                payload: &payload,
+            }
+            .into(),
+            Packet::Disconnect,
+        ];
+
+        let mut stream = Vec::new();
+        for pkt in &packets {
+            write_packet(&mut stream, pkt).unwrap();
+        }
+        assert_eq!(stream.len(), 4 + 310 + 2);
+
+        let mut reader = Cursor::new(stream);
+        let mut buf = Vec::new();
+        for pkt in &packets {
+            assert_eq!(Ok(pkt), read_packet(&mut reader, &mut buf).as_ref());
+        }
+        match read_packet(&mut reader, &mut buf) {
+            Err(Error::IoError(ErrorKind::UnexpectedEof, _)) => (),
+            other => panic!("Expected EOF, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn truncated() {
+        let mut reader: &[u8] = &[0b01000000, 2, 0];
+        let mut buf = Vec::new();
+        match read_packet(&mut reader, &mut buf) {
+            Err(Error::IoError(ErrorKind::UnexpectedEof, _)) => (),
+            other => panic!("Expected EOF, got {:?}", other),
+        }
+
+        let mut reader: &[u8] = &[0b01000000, 0x80, 0x80, 0x80, 0x80, 0];
+        assert_eq!(
+            Err(Error::InvalidRemainingLength),
+            read_packet(&mut reader, &mut buf)
+        );
+    }
+}
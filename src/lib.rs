@@ -33,7 +33,7 @@
 //! let mut incomplete = encoded.split_at(10).0;
 //! assert_eq!(Ok(None), decode_slice(&mut incomplete));
 //! let mut garbage = BytesMut::from(&[0u8,0,0,0] as &[u8]);
-//! assert_eq!(Err(Error::InvalidHeader), decode_slice(&mut garbage));
+//! assert_eq!(Err(Error::InvalidPacketType(0)), decode_slice(&mut garbage));
 //! ```
 //!
 //! [MQTT 3.1]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html
@@ -49,13 +49,28 @@
 #[cfg(feature = "std")]
 extern crate std;
 
+#[cfg(feature = "std")]
+mod bytes_packet;
 mod connect;
 mod decoder;
+#[cfg(feature = "std")]
+mod dissect;
+#[cfg(feature = "embedded-io")]
+pub mod embedded_io;
 mod encoder;
+#[cfg(feature = "futures-io")]
+pub mod futures_io;
+#[cfg(feature = "std")]
+mod io;
 mod packet;
+#[cfg(feature = "std")]
+mod pcap;
 mod publish;
+#[cfg(feature = "std")]
+mod sequence;
 mod subscribe;
 mod utils;
+mod validate;
 
 // Proptest does not currently support borrowed data in strategies:
 // https://github.com/AltSysrq/proptest/issues/9
@@ -69,10 +84,31 @@ mod encoder_test;
 
 pub use crate::{
     connect::{Connack, Connect, ConnectReturnCode, LastWill, Protocol},
-    decoder::{clone_packet, decode_slice},
-    encoder::encode_slice,
+    decoder::{
+        clone_packet, decode_from_client, decode_from_server, decode_slice,
+        decode_slice_with_limit, decode_split, decode_split_with_limit, peek_header, FixedHeader,
+        PacketIter,
+    },
+    encoder::{
+        encode_fixed_header, encode_many, encode_publish_header, encode_slice,
+        encode_slice_validated, set_publish_dup, set_publish_pid,
+    },
     packet::{Packet, PacketType},
     publish::Publish,
     subscribe::{Suback, Subscribe, SubscribeReturnCodes, SubscribeTopic, Unsubscribe},
     utils::{Error, Pid, QoS, QosPid},
+    validate::Violation,
+};
+
+#[cfg(feature = "std")]
+pub use crate::{
+    bytes_packet::{
+        decode_bytes, decode_bytes_mut, decode_bytes_mut_with_limit, decode_bytes_with_limit,
+        BytesConnect, BytesLastWill, BytesPacket, BytesPublish,
+    },
+    dissect::{dissect, Dissection, Field, FieldValue},
+    encoder::encode_many_buf,
+    io::{read_packet, read_packet_with_limit, write_packet},
+    pcap::{Direction, PcapWriter},
+    sequence::{SequenceChecker, SequenceViolation},
 };
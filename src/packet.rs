@@ -1,4 +1,7 @@
 use crate::*;
+use core::fmt;
+#[cfg(feature = "derive")]
+use serde::{Deserialize, Serialize};
 
 /// Base enum for all MQTT packet types.
 ///
@@ -25,12 +28,16 @@ use crate::*;
 /// [`encode()`]: fn.encode.html
 /// [`decode_slice()`]: fn.decode_slice.html
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Packet<'a> {
     /// [MQTT 3.1](http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718028)
+    #[cfg_attr(feature = "derive", serde(borrow))]
     Connect(Connect<'a>),
     /// [MQTT 3.2](http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718033)
     Connack(Connack),
     /// [MQTT 3.3](http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718037)
+    #[cfg_attr(feature = "derive", serde(borrow))]
     Publish(Publish<'a>),
     /// [MQTT 3.4](http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718043)
     Puback(Pid),
@@ -78,6 +85,143 @@ impl<'a> Packet<'a> {
             Packet::Disconnect => PacketType::Disconnect,
         }
     }
+
+    /// The reply that the protocol mandates for this received packet, if any.
+    ///
+    /// `grant` decides the return code of each topic of a `Subscribe`, and is ignored for the other
+    /// packets. `Connect` gets no reply here, because accepting it is up to the server.
+    ///
+    /// ```
+    /// # use mqttrs::*;
+    /// let pid = Pid::new();
+    /// let publish = Publish {
+    ///     dup: false,
+    ///     qospid: QosPid::ExactlyOnce(pid),
+    ///     retain: false,
+    ///     topic_name: "a/b",
+    ///     payload: b"hello",
+    /// };
+    /// let grant_all = |topic: &SubscribeTopic| SubscribeReturnCodes::Success(topic.qos);
+    /// let reply = Packet::from(publish).expected_response(grant_all);
+    /// assert_eq!(Some(Packet::Pubrec(pid)), reply);
+    /// assert_eq!(None, Packet::Puback(pid).expected_response(grant_all));
+    /// ```
+    pub fn expected_response<F>(&self, grant: F) -> Option<Packet<'static>>
+    where
+        F: FnMut(&SubscribeTopic) -> SubscribeReturnCodes,
+    {
+        match self {
+            Packet::Publish(publish) => match publish.qospid {
+                QosPid::AtMostOnce => None,
+                QosPid::AtLeastOnce(pid) => Some(Packet::Puback(pid)),
+                QosPid::ExactlyOnce(pid) => Some(Packet::Pubrec(pid)),
+            },
+            Packet::Pubrec(pid) => Some(Packet::Pubrel(*pid)),
+            Packet::Pubrel(pid) => Some(Packet::Pubcomp(*pid)),
+            Packet::Subscribe(subscribe) => Some(Packet::Suback(Suback::new(
+                subscribe.pid,
+                subscribe.topics.iter().map(grant).collect(),
+            ))),
+            Packet::Unsubscribe(unsubscribe) => Some(Packet::Unsuback(unsubscribe.pid)),
+            Packet::Pingreq => Some(Packet::Pingresp),
+            Packet::Connect(_)
+            | Packet::Connack(_)
+            | Packet::Puback(_)
+            | Packet::Pubcomp(_)
+            | Packet::Suback(_)
+            | Packet::Unsuback(_)
+            | Packet::Pingresp
+            | Packet::Disconnect => None,
+        }
+    }
+}
+
+impl fmt::Display for Packet<'_> {
+    /// Compact, human-oriented format, for example `PUBLISH q1 pid=7 retain topic="a/b" 12B`.
+    ///
+    /// Use `{:?}` to get all the details.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.get_type())?;
+        match self {
+            Packet::Connect(c) => {
+                write!(
+                    f,
+                    " {:?} keep_alive={} client_id={:?}",
+                    c.protocol, c.keep_alive, c.client_id
+                )?;
+                if c.clean_session {
+                    write!(f, " clean_session")?;
+                }
+                if let Some(w) = &c.last_will {
+                    write!(f, " will=({} ", w.qos)?;
+                    if w.retain {
+                        write!(f, "retain ")?;
+                    }
+                    write!(f, "topic={:?} {}B)", w.topic, w.message.len())?;
+                }
+                if let Some(u) = c.username {
+                    write!(f, " username={:?}", u)?;
+                }
+                if let Some(p) = c.password {
+                    write!(f, " password={}B", p.len())?;
+                }
+                Ok(())
+            }
+            Packet::Connack(c) => {
+                write!(f, " {:?}", c.code)?;
+                if c.session_present {
+                    write!(f, " session_present")?;
+                }
+                Ok(())
+            }
+            Packet::Publish(p) => {
+                write!(f, " {}", p.qospid.qos())?;
+                if let Some(pid) = p.qospid.pid() {
+                    write!(f, " pid={}", pid)?;
+                }
+                if p.dup {
+                    write!(f, " dup")?;
+                }
+                if p.retain {
+                    write!(f, " retain")?;
+                }
+                write!(f, " topic={:?} {}B", p.topic_name, p.payload.len())
+            }
+            Packet::Puback(pid)
+            | Packet::Pubrec(pid)
+            | Packet::Pubrel(pid)
+            | Packet::Pubcomp(pid)
+            | Packet::Unsuback(pid) => write!(f, " pid={}", pid),
+            Packet::Subscribe(s) => {
+                write!(f, " pid={} topics=[", s.pid)?;
+                for (i, t) in s.topics.iter().enumerate() {
+                    let sep = if i > 0 { ", " } else { "" };
+                    write!(f, "{}{:?} {}", sep, t.topic_path.as_str(), t.qos)?;
+                }
+                write!(f, "]")
+            }
+            Packet::Suback(s) => {
+                write!(f, " pid={} return_codes=[", s.pid)?;
+                for (i, rc) in s.return_codes.iter().enumerate() {
+                    let sep = if i > 0 { ", " } else { "" };
+                    match rc {
+                        SubscribeReturnCodes::Success(qos) => write!(f, "{}{}", sep, qos)?,
+                        SubscribeReturnCodes::Failure => write!(f, "{}failure", sep)?,
+                    }
+                }
+                write!(f, "]")
+            }
+            Packet::Unsubscribe(u) => {
+                write!(f, " pid={} topics=[", u.pid)?;
+                for (i, t) in u.topics.iter().enumerate() {
+                    let sep = if i > 0 { ", " } else { "" };
+                    write!(f, "{}{:?}", sep, t.as_str())?;
+                }
+                write!(f, "]")
+            }
+            Packet::Pingreq | Packet::Pingresp | Packet::Disconnect => Ok(()),
+        }
+    }
 }
 
 macro_rules! packet_from_borrowed {
@@ -108,6 +252,8 @@ packet_from!(Suback, Connack, Subscribe, Unsubscribe);
 
 /// Packet type variant, without the associated data.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum PacketType {
     Connect,
     Connack,
@@ -124,3 +270,230 @@ pub enum PacketType {
     Pingresp,
     Disconnect,
 }
+
+impl PacketType {
+    /// Whether a client is allowed to send this packet type to a server.
+    pub fn sent_by_client(self) -> bool {
+        !matches!(
+            self,
+            PacketType::Connack | PacketType::Suback | PacketType::Unsuback | PacketType::Pingresp
+        )
+    }
+
+    /// Whether a server is allowed to send this packet type to a client.
+    pub fn sent_by_server(self) -> bool {
+        !matches!(
+            self,
+            PacketType::Connect
+                | PacketType::Subscribe
+                | PacketType::Unsubscribe
+                | PacketType::Pingreq
+                | PacketType::Disconnect
+        )
+    }
+}
+
+impl fmt::Display for PacketType {
+    /// Formats as the upper-case name used by the spec, for example `PUBLISH`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            PacketType::Connect => "CONNECT",
+            PacketType::Connack => "CONNACK",
+            PacketType::Publish => "PUBLISH",
+            PacketType::Puback => "PUBACK",
+            PacketType::Pubrec => "PUBREC",
+            PacketType::Pubrel => "PUBREL",
+            PacketType::Pubcomp => "PUBCOMP",
+            PacketType::Subscribe => "SUBSCRIBE",
+            PacketType::Suback => "SUBACK",
+            PacketType::Unsubscribe => "UNSUBSCRIBE",
+            PacketType::Unsuback => "UNSUBACK",
+            PacketType::Pingreq => "PINGREQ",
+            PacketType::Pingresp => "PINGRESP",
+            PacketType::Disconnect => "DISCONNECT",
+        };
+        f.write_str(name)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+    use core::convert::TryFrom;
+    use subscribe::{LimitedString, LimitedVec};
+
+    #[test]
+    fn expected_response() {
+        let pid = Pid::try_from(7).unwrap();
+        let topics = LimitedVec::from(vec![
+            SubscribeTopic {
+                topic_path: LimitedString::from("a/#"),
+                qos: QoS::ExactlyOnce,
+            },
+            SubscribeTopic {
+                topic_path: LimitedString::from("denied"),
+                qos: QoS::AtMostOnce,
+            },
+        ]);
+        let subscribe = Packet::Subscribe(Subscribe::new(pid, topics));
+        let suback = subscribe.expected_response(|t| match t.topic_path.as_str() {
+            "denied" => SubscribeReturnCodes::Failure,
+            // Downgraded to QoS 1
+            _ => SubscribeReturnCodes::Success(QoS::AtLeastOnce),
+        });
+        let return_codes = LimitedVec::from(vec![
+            SubscribeReturnCodes::Success(QoS::AtLeastOnce),
+            SubscribeReturnCodes::Failure,
+        ]);
+        assert_eq!(Some(Packet::Suback(Suback::new(pid, return_codes))), suback);
+
+        let grant = |_: &SubscribeTopic| -> SubscribeReturnCodes { unreachable!() };
+        let t = vec![
+            (Packet::Pubrec(pid), Some(Packet::Pubrel(pid))),
+            (Packet::Pubrel(pid), Some(Packet::Pubcomp(pid))),
+            (Packet::Pingreq, Some(Packet::Pingresp)),
+            (
+                Unsubscribe::new(pid, LimitedVec::new()).into(),
+                Some(Packet::Unsuback(pid)),
+            ),
+            (Packet::Pubcomp(pid), None),
+            (Packet::Disconnect, None),
+        ];
+        for (packet, response) in t {
+            assert_eq!(response, packet.expected_response(grant), "{}", packet);
+        }
+    }
+
+    #[test]
+    fn display() {
+        let pid = Pid::try_from(7).unwrap();
+        let t: Vec<(Packet, &str)> = vec![
+            (
+                Publish {
+                    dup: false,
+                    qospid: QosPid::AtLeastOnce(pid),
+                    retain: true,
+                    topic_name: "a/b",
+                    payload: &[0; 12],
+                }
+                .into(),
+                r#"PUBLISH q1 pid=7 retain topic="a/b" 12B"#,
+            ),
+            (
+                Connect {
+                    protocol: Protocol::MQTT311,
+                    keep_alive: 30,
+                    client_id: "c",
+                    clean_session: true,
+                    last_will: Some(LastWill {
+                        topic: "w",
+                        message: b"bye",
+                        qos: QoS::AtMostOnce,
+                        retain: false,
+                    }),
+                    username: Some("u"),
+                    password: Some(b"pw"),
+                }
+                .into(),
+                r#"CONNECT MQTT311 keep_alive=30 client_id="c" clean_session will=(q0 topic="w" 3B) username="u" password=2B"#,
+            ),
+            (
+                Connack {
+                    session_present: true,
+                    code: ConnectReturnCode::NotAuthorized,
+                }
+                .into(),
+                "CONNACK NotAuthorized session_present",
+            ),
+            (Packet::Pubrel(pid), "PUBREL pid=7"),
+            (
+                Subscribe::new(
+                    pid,
+                    LimitedVec::from(vec![
+                        SubscribeTopic {
+                            topic_path: LimitedString::from("a/#"),
+                            qos: QoS::ExactlyOnce,
+                        },
+                        SubscribeTopic {
+                            topic_path: LimitedString::from("b"),
+                            qos: QoS::AtMostOnce,
+                        },
+                    ]),
+                )
+                .into(),
+                r#"SUBSCRIBE pid=7 topics=["a/#" q2, "b" q0]"#,
+            ),
+            (
+                Suback::new(
+                    pid,
+                    LimitedVec::from(vec![
+                        SubscribeReturnCodes::Success(QoS::AtLeastOnce),
+                        SubscribeReturnCodes::Failure,
+                    ]),
+                )
+                .into(),
+                "SUBACK pid=7 return_codes=[q1, failure]",
+            ),
+            (
+                Unsubscribe::new(pid, LimitedVec::from(vec![LimitedString::from("a/b")])).into(),
+                r#"UNSUBSCRIBE pid=7 topics=["a/b"]"#,
+            ),
+            (Packet::Pingreq, "PINGREQ"),
+        ];
+        for (pkt, s) in t {
+            assert_eq!(s, format!("{}", pkt));
+        }
+
+        assert_eq!(
+            "invalid QoS 3, must be 0, 1 or 2",
+            format!("{}", Error::InvalidQos(3))
+        );
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn serde_json() {
+        let publish: Packet = Publish {
+            dup: false,
+            qospid: QosPid::AtLeastOnce(Pid::try_from(7).unwrap()),
+            retain: true,
+            topic_name: "a/b",
+            payload: b"hi",
+        }
+        .into();
+        assert_eq!(
+            r#"{"Publish":{"dup":false,"qospid":{"AtLeastOnce":7},"retain":true,"topic_name":"a/b","payload":[104,105]}}"#,
+            serde_json::to_string(&publish).unwrap()
+        );
+        // JSON can only borrow bytes from a string without escapes, not from a number array
+        let json = r#"{"Publish":{"dup":false,"qospid":{"AtLeastOnce":7},"retain":true,"topic_name":"a/b","payload":"hi"}}"#;
+        assert_eq!(publish, serde_json::from_str::<Packet>(json).unwrap());
+        let json = serde_json::to_string(&publish).unwrap();
+        assert!(serde_json::from_str::<Packet>(&json).is_err());
+
+        // Borrowed strings are deserialized without copying
+        let connect: Packet = Connect {
+            protocol: Protocol::MQTT311,
+            keep_alive: 30,
+            client_id: "client",
+            clean_session: true,
+            last_will: None,
+            username: Some("user"),
+            password: None,
+        }
+        .into();
+        let json = serde_json::to_string(&connect).unwrap();
+        assert_eq!(connect, serde_json::from_str::<Packet>(&json).unwrap());
+
+        let suback: Packet = Suback::new(
+            Pid::try_from(3).unwrap(),
+            vec![
+                SubscribeReturnCodes::Success(QoS::AtLeastOnce),
+                SubscribeReturnCodes::Failure,
+            ],
+        )
+        .into();
+        let json = serde_json::to_string(&suback).unwrap();
+        assert_eq!(suback, serde_json::from_str::<Packet>(&json).unwrap());
+    }
+}
@@ -0,0 +1,341 @@
+use crate::{io::encode_vec, *};
+use core::time::Duration;
+use std::{
+    io::Write,
+    net::{Ipv4Addr, SocketAddrV4},
+    vec::Vec,
+};
+
+/// Direction of recorded traffic, from the point of view of the recording device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Received bytes, typically fed to [decode_slice()].
+    ///
+    /// [decode_slice()]: fn.decode_slice.html
+    Inbound,
+    /// Sent bytes, typically produced by [encode_slice()].
+    ///
+    /// [encode_slice()]: fn.encode_slice.html
+    Outbound,
+}
+
+/// Records MQTT traffic into a [pcapng] file, which can be opened in Wireshark.
+///
+/// The MQTT bytes are wrapped in synthetic IPv4 and TCP headers, between a local and a remote
+/// address. A TCP handshake is recorded before the first packet, and the direction is also stored
+/// in each packet's `epb_flags`.
+///
+/// ```
+/// # use mqttrs::*;
+/// # use std::time::Duration;
+/// let mut pcap = PcapWriter::new(Vec::new()).unwrap();
+/// let now = Duration::from_secs(1_600_000_000);
+/// pcap.write_packet(Direction::Outbound, now, &Packet::Pingreq).unwrap();
+/// pcap.write_bytes(Direction::Inbound, now, &[0b11010000, 0]).unwrap();
+/// let file: Vec<u8> = pcap.into_inner().unwrap();
+/// ```
+///
+/// [pcapng]: https://www.ietf.org/archive/id/draft-tuexen-opsawg-pcapng-05.html
+#[derive(Debug)]
+pub struct PcapWriter<W: Write> {
+    writer: W,
+    local: SocketAddrV4,
+    remote: SocketAddrV4,
+    /// Next sequence number sent by the local and remote side, once the handshake is recorded.
+    seqs: Option<(u32, u32)>,
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Start a pcapng file, using `127.0.0.1:49152` as the local address and `127.0.0.2:1883`
+    /// as the remote one.
+    pub fn new(writer: W) -> Result<Self, Error> {
+        let local = SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 49152);
+        let remote = SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 2), 1883);
+        Self::with_addrs(writer, local, remote)
+    }
+
+    /// Start a pcapng file, with the given synthetic addresses.
+    ///
+    /// Wireshark recognizes MQTT on TCP port 1883 by default.
+    pub fn with_addrs(
+        mut writer: W,
+        local: SocketAddrV4,
+        remote: SocketAddrV4,
+    ) -> Result<Self, Error> {
+        // Section header block
+        let mut shb = Vec::new();
+        shb.extend_from_slice(&0x1A2B_3C4Du32.to_le_bytes());
+        shb.extend_from_slice(&1u16.to_le_bytes());
+        shb.extend_from_slice(&0u16.to_le_bytes());
+        shb.extend_from_slice(&(-1i64).to_le_bytes());
+        write_block(&mut writer, 0x0A0D_0D0A, &shb)?;
+
+        // Interface description block, raw IPv4 with microsecond timestamps
+        let mut idb = Vec::new();
+        idb.extend_from_slice(&LINKTYPE_IPV4.to_le_bytes());
+        idb.extend_from_slice(&0u16.to_le_bytes());
+        idb.extend_from_slice(&0u32.to_le_bytes());
+        write_block(&mut writer, 1, &idb)?;
+
+        Ok(PcapWriter {
+            writer,
+            local,
+            remote,
+            seqs: None,
+        })
+    }
+
+    /// Record an encoded [Packet].
+    ///
+    /// [Packet]: enum.Packet.html
+    pub fn write_packet(
+        &mut self,
+        direction: Direction,
+        timestamp: Duration,
+        packet: &Packet,
+    ) -> Result<(), Error> {
+        let mut buf = Vec::new();
+        let len = encode_vec(packet, &mut buf)?;
+        self.write_bytes(direction, timestamp, &buf[..len])
+    }
+
+    /// Record raw MQTT stream bytes, for example a buffer given to [decode_slice()].
+    ///
+    /// `timestamp` is the time since the Unix epoch.
+    ///
+    /// [decode_slice()]: fn.decode_slice.html
+    pub fn write_bytes(
+        &mut self,
+        direction: Direction,
+        timestamp: Duration,
+        bytes: &[u8],
+    ) -> Result<(), Error> {
+        let (local_seq, remote_seq) = match self.seqs {
+            Some(seqs) => seqs,
+            None => {
+                let (l, r) = (1000, 2000);
+                self.write_segment(Direction::Outbound, timestamp, l, 0, SYN, &[])?;
+                self.write_segment(Direction::Inbound, timestamp, r, l + 1, SYN | ACK, &[])?;
+                self.write_segment(Direction::Outbound, timestamp, l + 1, r + 1, ACK, &[])?;
+                (l + 1, r + 1)
+            }
+        };
+        let (mut seq, ack) = match direction {
+            Direction::Outbound => (local_seq, remote_seq),
+            Direction::Inbound => (remote_seq, local_seq),
+        };
+        for chunk in bytes.chunks(MAX_SEGMENT) {
+            self.write_segment(direction, timestamp, seq, ack, PSH | ACK, chunk)?;
+            seq = seq.wrapping_add(chunk.len() as u32);
+        }
+        self.seqs = Some(match direction {
+            Direction::Outbound => (seq, remote_seq),
+            Direction::Inbound => (local_seq, seq),
+        });
+        Ok(())
+    }
+
+    /// Flush and return the underlying writer.
+    pub fn into_inner(mut self) -> Result<W, Error> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_segment(
+        &mut self,
+        direction: Direction,
+        timestamp: Duration,
+        seq: u32,
+        ack: u32,
+        flags: u8,
+        payload: &[u8],
+    ) -> Result<(), Error> {
+        let (src, dst) = match direction {
+            Direction::Outbound => (self.local, self.remote),
+            Direction::Inbound => (self.remote, self.local),
+        };
+        let frame = ipv4_tcp(src, dst, seq, ack, flags, payload);
+
+        // Enhanced packet block
+        let micros = timestamp.as_secs() * 1_000_000 + timestamp.subsec_micros() as u64;
+        let mut epb = Vec::with_capacity(frame.len() + 40);
+        epb.extend_from_slice(&0u32.to_le_bytes());
+        epb.extend_from_slice(&((micros >> 32) as u32).to_le_bytes());
+        epb.extend_from_slice(&(micros as u32).to_le_bytes());
+        epb.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        epb.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        epb.extend_from_slice(&frame);
+        epb.resize((epb.len() + 3) & !3, 0);
+        // epb_flags option: inbound = 1, outbound = 2
+        let dir_flag: u32 = match direction {
+            Direction::Inbound => 1,
+            Direction::Outbound => 2,
+        };
+        epb.extend_from_slice(&2u16.to_le_bytes());
+        epb.extend_from_slice(&4u16.to_le_bytes());
+        epb.extend_from_slice(&dir_flag.to_le_bytes());
+        epb.extend_from_slice(&[0; 4]); // opt_endofopt
+        write_block(&mut self.writer, 6, &epb)
+    }
+}
+
+const LINKTYPE_IPV4: u16 = 228;
+/// Keep the IPv4 total length within 16 bits.
+const MAX_SEGMENT: usize = 65535 - 40;
+
+const SYN: u8 = 0x02;
+const PSH: u8 = 0x08;
+const ACK: u8 = 0x10;
+
+/// Write a pcapng block, `body` must be padded to 32 bits.
+fn write_block(writer: &mut impl Write, typ: u32, body: &[u8]) -> Result<(), Error> {
+    let len = (body.len() + 12) as u32;
+    writer.write_all(&typ.to_le_bytes())?;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(body)?;
+    writer.write_all(&len.to_le_bytes())?;
+    Ok(())
+}
+
+/// Build an IPv4 datagram containing a TCP segment, with valid checksums.
+fn ipv4_tcp(
+    src: SocketAddrV4,
+    dst: SocketAddrV4,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    payload: &[u8],
+) -> Vec<u8> {
+    let tcp_len = 20 + payload.len();
+    let mut p = Vec::with_capacity(20 + tcp_len);
+
+    // IPv4 header
+    p.extend_from_slice(&[0x45, 0]);
+    p.extend_from_slice(&((20 + tcp_len) as u16).to_be_bytes());
+    p.extend_from_slice(&[0, 0, 0x40, 0, 64, 6, 0, 0]); // id, don't fragment, ttl, proto
+    p.extend_from_slice(&src.ip().octets());
+    p.extend_from_slice(&dst.ip().octets());
+    let sum = checksum(&[&p[..20]]);
+    p[10..12].copy_from_slice(&sum.to_be_bytes());
+
+    // TCP header
+    p.extend_from_slice(&src.port().to_be_bytes());
+    p.extend_from_slice(&dst.port().to_be_bytes());
+    p.extend_from_slice(&seq.to_be_bytes());
+    p.extend_from_slice(&(if flags & ACK != 0 { ack } else { 0 }).to_be_bytes());
+    p.extend_from_slice(&[0x50, flags, 0xff, 0xff, 0, 0, 0, 0]);
+    p.extend_from_slice(payload);
+    let mut pseudo = Vec::with_capacity(12);
+    pseudo.extend_from_slice(&src.ip().octets());
+    pseudo.extend_from_slice(&dst.ip().octets());
+    pseudo.extend_from_slice(&[0, 6]);
+    pseudo.extend_from_slice(&(tcp_len as u16).to_be_bytes());
+    let sum = checksum(&[&pseudo, &p[20..]]);
+    p[36..38].copy_from_slice(&sum.to_be_bytes());
+    p
+}
+
+/// Internet checksum over the concatenation of `parts`, each of even length except the last.
+fn checksum(parts: &[&[u8]]) -> u16 {
+    let mut sum: u32 = 0;
+    for part in parts {
+        for pair in part.chunks(2) {
+            let word = match pair {
+                [a, b] => u16::from_be_bytes([*a, *b]),
+                [a] => u16::from_be_bytes([*a, 0]),
+                _ => unreachable!(),
+            };
+            sum += word as u32;
+        }
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod test {
+    use super::checksum;
+    use crate::*;
+    use core::{convert::TryInto, time::Duration};
+
+    /// Split a pcapng file into (block type, body) pairs.
+    fn blocks(mut data: &[u8]) -> Vec<(u32, &[u8])> {
+        let mut blocks = Vec::new();
+        while !data.is_empty() {
+            let typ = u32::from_le_bytes(data[..4].try_into().unwrap());
+            let len = u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize;
+            assert_eq!(&data[4..8], &data[len - 4..len]);
+            blocks.push((typ, &data[8..len - 4]));
+            data = &data[len..];
+        }
+        blocks
+    }
+
+    #[test]
+    fn session() {
+        let mut pcap = PcapWriter::new(Vec::new()).unwrap();
+        let ts = Duration::new(1_600_000_000, 123_456_000);
+        let publish: Packet = Publish {
+            dup: false,
+            qospid: QosPid::AtMostOnce,
+            retain: false,
+            topic_name: "a",
+            payload: b"hello",
+        }
+        .into();
+        pcap.write_packet(Direction::Outbound, ts, &publish)
+            .unwrap();
+        pcap.write_bytes(Direction::Inbound, ts, &[0b11010000, 0])
+            .unwrap();
+        let data = pcap.into_inner().unwrap();
+
+        let blocks = blocks(&data);
+        let types: Vec<u32> = blocks.iter().map(|b| b.0).collect();
+        assert_eq!(types, [0x0A0D0D0A, 1, 6, 6, 6, 6, 6]);
+
+        // Publish segment
+        let epb = blocks[5].1;
+        let micros = 1_600_000_000_123_456u64;
+        assert_eq!(&epb[4..8], &((micros >> 32) as u32).to_le_bytes());
+        assert_eq!(&epb[8..12], &(micros as u32).to_le_bytes());
+        let len = u32::from_le_bytes(epb[12..16].try_into().unwrap()) as usize;
+        assert_eq!(len, 40 + 10);
+        let frame = &epb[20..20 + len];
+        assert_eq!(checksum(&[&frame[..20]]), 0);
+        assert_eq!(&frame[20..22], &49152u16.to_be_bytes());
+        assert_eq!(&frame[22..24], &1883u16.to_be_bytes());
+        assert_eq!(&frame[24..28], &1001u32.to_be_bytes());
+        assert_eq!(decode_slice(&frame[40..]), Ok(Some(publish)));
+        // Outbound flag
+        assert_eq!(&epb[epb.len() - 8..epb.len() - 4], &2u32.to_le_bytes());
+
+        // Pingresp segment, acknowledging the publish
+        let frame = &blocks[6].1[20..];
+        assert_eq!(&frame[20..22], &1883u16.to_be_bytes());
+        assert_eq!(&frame[24..28], &2001u32.to_be_bytes());
+        assert_eq!(&frame[28..32], &1011u32.to_be_bytes());
+        assert_eq!(&frame[40..42], &[0b11010000, 0]);
+    }
+
+    #[test]
+    fn flush_error() {
+        /// Accepts writes but fails to flush them.
+        struct Unflushable(Vec<u8>);
+        impl std::io::Write for Unflushable {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.write(buf)
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Err(std::io::ErrorKind::BrokenPipe.into())
+            }
+        }
+
+        let pcap = PcapWriter::new(Unflushable(Vec::new())).unwrap();
+        match pcap.into_inner() {
+            Err(Error::IoError(kind, _)) => assert_eq!(kind, std::io::ErrorKind::BrokenPipe),
+            res => panic!("unexpected {:?}", res.map(|w| w.0)),
+        }
+    }
+}
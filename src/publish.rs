@@ -1,4 +1,8 @@
+#[cfg(feature = "defmt")]
+use crate::utils::BytesPrefix;
 use crate::{decoder::*, encoder::*, *};
+#[cfg(feature = "derive")]
+use serde::{Deserialize, Serialize};
 
 // use alloc::{string::String, vec::Vec};
 use heapless::{String, Vec, consts};
@@ -8,28 +12,49 @@ use heapless::{String, Vec, consts};
 ///
 /// [MQTT 3.3]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718037
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
 pub struct Publish<'a> {
     pub dup: bool,
     pub qospid: QosPid,
     pub retain: bool,
     pub topic_name: &'a str,
+    #[cfg_attr(feature = "derive", serde(borrow, with = "serde_bytes"))]
     pub payload: &'a [u8],
 }
 
+#[cfg(feature = "defmt")]
+impl defmt::Format for Publish<'_> {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "Publish {{ dup: {}, qospid: {}, retain: {}, topic_name: {=str}, payload: {} }}",
+            self.dup,
+            self.qospid,
+            self.retain,
+            self.topic_name,
+            BytesPrefix(self.payload)
+        )
+    }
+}
+
 impl<'a> Publish<'a> {
     pub(crate) fn from_buffer(
-        header: &Header,
+        header: &FixedHeader,
         remaining_len: usize,
-        buf: &'a [u8],
+        buf: &Input<'a>,
         offset: &mut usize,
     ) -> Result<Self, Error> {
         let payload_end = *offset + remaining_len;
-        let topic_name = read_str(buf, offset)?;
+        let topic_name = read_str(buf, offset, PacketType::Publish, "topic")?;
 
         let qospid = match header.qos {
             QoS::AtMostOnce => QosPid::AtMostOnce,
-            QoS::AtLeastOnce => QosPid::AtLeastOnce(Pid::from_buffer(buf, offset)?),
-            QoS::ExactlyOnce => QosPid::ExactlyOnce(Pid::from_buffer(buf, offset)?),
+            QoS::AtLeastOnce => {
+                QosPid::AtLeastOnce(Pid::from_buffer(buf, offset, PacketType::Publish)?)
+            }
+            QoS::ExactlyOnce => {
+                QosPid::ExactlyOnce(Pid::from_buffer(buf, offset, PacketType::Publish)?)
+            }
         };
 
         Ok(Publish {
@@ -37,10 +62,45 @@ impl<'a> Publish<'a> {
             qospid,
             retain: header.retain,
             topic_name,
-            payload: &buf[*offset..payload_end],
+            payload: buf.slice(*offset, payload_end)?,
         })
     }
     pub(crate) fn to_buffer(&self, buf: &mut [u8], offset: &mut usize) -> Result<usize, Error> {
+        let header_len = self.header_to_buffer(buf, offset)?;
+
+        // Payload
+        check_remaining(buf, offset, self.payload.len())?;
+        buf[*offset..*offset + self.payload.len()].copy_from_slice(self.payload);
+        *offset += self.payload.len();
+
+        Ok(header_len + self.payload.len())
+    }
+
+    /// Length of the variable header: topic (2+len) + pid (0/2).
+    fn variable_header_len(&self) -> Result<usize, Error> {
+        Ok(string_len(self.topic_name)?
+            + match self.qospid {
+                QosPid::AtMostOnce => 0,
+                _ => 2,
+            })
+    }
+
+    pub(crate) fn remaining_len(&self) -> Result<usize, Error> {
+        Ok(self.variable_header_len()? + self.payload.len())
+    }
+
+    /// Length of everything up to the payload.
+    pub(crate) fn header_len(&self) -> Result<usize, Error> {
+        let variable_len = self.variable_header_len()?;
+        Ok(1 + length_size(variable_len + self.payload.len())? + variable_len)
+    }
+
+    /// Write everything up to the payload, returning the number of bytes written.
+    pub(crate) fn header_to_buffer(
+        &self,
+        buf: &mut [u8],
+        offset: &mut usize,
+    ) -> Result<usize, Error> {
         // Header
         let mut header: u8 = match self.qospid {
             QosPid::AtMostOnce => 0b00110000,
@@ -53,18 +113,14 @@ impl<'a> Publish<'a> {
         if self.retain {
             header |= 0b00000001 as u8;
         };
-        check_remaining(buf, offset, 1)?;
-        write_u8(buf, offset, header)?;
 
-        // Length: topic (2+len) + pid (0/2) + payload (len)
-        let length = self.topic_name.len()
-            + match self.qospid {
-                QosPid::AtMostOnce => 2,
-                _ => 4,
-            }
-            + self.payload.len();
+        let variable_len = self.variable_header_len()?;
+        let length = variable_len + self.payload.len();
+        let header_len = 1 + length_size(length)? + variable_len;
+        check_remaining(buf, offset, header_len)?;
 
-        let write_len = write_length(buf, offset, length)? + 1;
+        write_u8(buf, offset, header)?;
+        write_length_bytes(buf, offset, length)?;
 
         // Topic
         write_string(buf, offset, self.topic_name)?;
@@ -76,11 +132,6 @@ impl<'a> Publish<'a> {
             QosPid::ExactlyOnce(pid) => pid.to_buffer(buf, offset)?,
         }
 
-        // Payload
-        for &byte in self.payload {
-            write_u8(buf, offset, byte)?;
-        }
-
-        Ok(write_len)
+        Ok(header_len)
     }
 }
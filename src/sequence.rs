@@ -0,0 +1,378 @@
+use crate::*;
+use core::fmt;
+use std::collections::HashMap;
+
+/// A protocol sequence rule broken on a connection, found by [SequenceChecker].
+///
+/// [SequenceChecker]: struct.SequenceChecker.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SequenceViolation {
+    /// Packet that the sender's side is not allowed to send, like a `Connack` from a client.
+    WrongDirection(PacketType),
+    /// Packet sent before the client's `Connect`, by either side.
+    BeforeConnect(PacketType),
+    /// Second `Connect` from the client.
+    SecondConnect,
+    /// Packet other than `Connack` sent by the server before its `Connack`.
+    BeforeConnack(PacketType),
+    /// Second `Connack` from the server.
+    SecondConnack,
+    /// Packet sent by the server after refusing the connection.
+    AfterRefusal(PacketType),
+    /// Packet sent by the client after its `Disconnect`.
+    AfterDisconnect(PacketType),
+    /// Publish, subscribe or unsubscribe reusing the pid of an exchange still in progress.
+    PidInUse(PacketType, Pid),
+    /// Acknowledgement or `Pubrel` for a pid that doesn't expect it.
+    UnknownPid(PacketType, Pid),
+}
+
+impl fmt::Display for SequenceViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SequenceViolation::WrongDirection(typ) if typ.sent_by_client() => {
+                write!(f, "{} can only be sent by a client", typ)
+            }
+            SequenceViolation::WrongDirection(typ) => {
+                write!(f, "{} can only be sent by a server", typ)
+            }
+            SequenceViolation::BeforeConnect(typ) => write!(f, "{} before CONNECT", typ),
+            SequenceViolation::SecondConnect => write!(f, "second CONNECT"),
+            SequenceViolation::BeforeConnack(typ) => write!(f, "{} before CONNACK", typ),
+            SequenceViolation::SecondConnack => write!(f, "second CONNACK"),
+            SequenceViolation::AfterRefusal(typ) => {
+                write!(f, "{} after the connection was refused", typ)
+            }
+            SequenceViolation::AfterDisconnect(typ) => write!(f, "{} after DISCONNECT", typ),
+            SequenceViolation::PidInUse(typ, pid) => {
+                write!(f, "{} reuses pid {} which is still in use", typ, pid.get())
+            }
+            SequenceViolation::UnknownPid(typ, pid) => {
+                write!(f, "{} for pid {} which doesn't expect it", typ, pid.get())
+            }
+        }
+    }
+}
+
+/// Observes the packets of one connection, and flags those that break the protocol sequence.
+///
+/// Feed it every packet, in the order they were sent, with [`from_client()`] or
+/// [`from_server()`]. A packet that breaks a rule is reported and otherwise ignored, so that the
+/// next ones are still checked against a consistent state.
+///
+/// It tracks the connection handshake and the pids of publish (QoS 1 and 2), subscribe and
+/// unsubscribe exchanges. Each connection starts without any exchange in progress, so
+/// retransmissions of a previous connection's packets are reported as unknown pids.
+///
+/// ```
+/// # use mqttrs::*;
+/// let mut seq = SequenceChecker::new();
+/// assert_eq!(
+///     Err(SequenceViolation::BeforeConnect(PacketType::Pingreq)),
+///     seq.from_client(&Packet::Pingreq)
+/// );
+/// let connect = Connect {
+///     protocol: Protocol::MQTT311,
+///     keep_alive: 30,
+///     client_id: "client",
+///     clean_session: true,
+///     last_will: None,
+///     username: None,
+///     password: None,
+/// };
+/// assert_eq!(Ok(()), seq.from_client(&connect.into()));
+/// assert_eq!(
+///     Err(SequenceViolation::UnknownPid(PacketType::Pubrel, Pid::new())),
+///     seq.from_client(&Packet::Pubrel(Pid::new()))
+/// );
+/// ```
+///
+/// [`from_client()`]: #method.from_client
+/// [`from_server()`]: #method.from_server
+#[derive(Debug, Clone, Default)]
+pub struct SequenceChecker {
+    connect: bool,
+    connack: Option<ConnectReturnCode>,
+    disconnect: bool,
+    /// Next packet expected in each exchange started by the client, by pid.
+    client_pids: HashMap<Pid, PacketType>,
+    /// Next packet expected in each exchange started by the server, by pid.
+    server_pids: HashMap<Pid, PacketType>,
+}
+
+impl SequenceChecker {
+    /// Start checking a new connection.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check a packet sent by the client.
+    pub fn from_client(&mut self, packet: &Packet) -> Result<(), SequenceViolation> {
+        let typ = packet.get_type();
+        if !typ.sent_by_client() {
+            return Err(SequenceViolation::WrongDirection(typ));
+        }
+        if self.disconnect {
+            return Err(SequenceViolation::AfterDisconnect(typ));
+        }
+        match (self.connect, typ) {
+            (false, PacketType::Connect) => (),
+            (false, _) => return Err(SequenceViolation::BeforeConnect(typ)),
+            (true, PacketType::Connect) => return Err(SequenceViolation::SecondConnect),
+            (true, _) => (),
+        }
+        self.track(packet, true)?;
+        match typ {
+            PacketType::Connect => self.connect = true,
+            PacketType::Disconnect => self.disconnect = true,
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// Check a packet sent by the server.
+    pub fn from_server(&mut self, packet: &Packet) -> Result<(), SequenceViolation> {
+        let typ = packet.get_type();
+        if !typ.sent_by_server() {
+            return Err(SequenceViolation::WrongDirection(typ));
+        }
+        if !self.connect {
+            return Err(SequenceViolation::BeforeConnect(typ));
+        }
+        match (self.connack, packet) {
+            (None, Packet::Connack(connack)) => self.connack = Some(connack.code),
+            (None, _) => return Err(SequenceViolation::BeforeConnack(typ)),
+            (Some(ConnectReturnCode::Accepted), Packet::Connack(_)) => {
+                return Err(SequenceViolation::SecondConnack)
+            }
+            (Some(ConnectReturnCode::Accepted), _) => self.track(packet, false)?,
+            (Some(_), _) => return Err(SequenceViolation::AfterRefusal(typ)),
+        }
+        Ok(())
+    }
+
+    /// Update the exchanges in progress with a packet, sent by the client if `client` is true.
+    fn track(&mut self, packet: &Packet, client: bool) -> Result<(), SequenceViolation> {
+        let typ = packet.get_type();
+        let (own, peer) = if client {
+            (&mut self.client_pids, &mut self.server_pids)
+        } else {
+            (&mut self.server_pids, &mut self.client_pids)
+        };
+        match packet {
+            Packet::Publish(publish) => {
+                let (pid, expected) = match publish.qospid {
+                    QosPid::AtMostOnce => return Ok(()),
+                    QosPid::AtLeastOnce(pid) => (pid, PacketType::Puback),
+                    QosPid::ExactlyOnce(pid) => (pid, PacketType::Pubrec),
+                };
+                match own.get(&pid) {
+                    None => {
+                        own.insert(pid, expected);
+                    }
+                    // Retransmission of a publish that wasn't acknowledged yet
+                    Some(&next) if publish.dup && next == expected => (),
+                    Some(_) => return Err(SequenceViolation::PidInUse(typ, pid)),
+                }
+            }
+            Packet::Subscribe(Subscribe { pid, .. }) => start(own, typ, *pid, PacketType::Suback)?,
+            Packet::Unsubscribe(Unsubscribe { pid, .. }) => {
+                start(own, typ, *pid, PacketType::Unsuback)?
+            }
+            Packet::Pubrel(pid) => advance(own, typ, *pid, Some(PacketType::Pubcomp))?,
+            Packet::Pubrec(pid) => advance(peer, typ, *pid, Some(PacketType::Pubrel))?,
+            Packet::Puback(pid) | Packet::Pubcomp(pid) | Packet::Unsuback(pid) => {
+                advance(peer, typ, *pid, None)?
+            }
+            Packet::Suback(Suback { pid, .. }) => advance(peer, typ, *pid, None)?,
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+/// Start an exchange, expecting `next` as the reply.
+fn start(
+    pids: &mut HashMap<Pid, PacketType>,
+    typ: PacketType,
+    pid: Pid,
+    next: PacketType,
+) -> Result<(), SequenceViolation> {
+    if pids.contains_key(&pid) {
+        return Err(SequenceViolation::PidInUse(typ, pid));
+    }
+    pids.insert(pid, next);
+    Ok(())
+}
+
+/// Continue an exchange that expects `typ`, ending it if there is no `next` packet.
+fn advance(
+    pids: &mut HashMap<Pid, PacketType>,
+    typ: PacketType,
+    pid: Pid,
+    next: Option<PacketType>,
+) -> Result<(), SequenceViolation> {
+    if pids.get(&pid) != Some(&typ) {
+        return Err(SequenceViolation::UnknownPid(typ, pid));
+    }
+    match next {
+        Some(next) => pids.insert(pid, next),
+        None => pids.remove(&pid),
+    };
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    fn connect() -> Packet<'static> {
+        Connect {
+            protocol: Protocol::MQTT311,
+            keep_alive: 30,
+            client_id: "test",
+            clean_session: true,
+            last_will: None,
+            username: None,
+            password: None,
+        }
+        .into()
+    }
+
+    fn connected() -> SequenceChecker {
+        let mut seq = SequenceChecker::new();
+        seq.from_client(&connect()).unwrap();
+        let connack = Connack {
+            session_present: false,
+            code: ConnectReturnCode::Accepted,
+        };
+        seq.from_server(&connack.into()).unwrap();
+        seq
+    }
+
+    fn publish(qospid: QosPid, dup: bool) -> Packet<'static> {
+        Publish {
+            dup,
+            qospid,
+            retain: false,
+            topic_name: "a/b",
+            payload: b"",
+        }
+        .into()
+    }
+
+    #[test]
+    fn handshake() {
+        let mut seq = SequenceChecker::new();
+        let connack: Packet = Connack {
+            session_present: false,
+            code: ConnectReturnCode::RefusedIdentifierRejected,
+        }
+        .into();
+        assert_eq!(
+            Err(SequenceViolation::BeforeConnect(PacketType::Connack)),
+            seq.from_server(&connack)
+        );
+
+        let mut seq = connected();
+        let pid = Pid::new();
+        assert_eq!(
+            Err(SequenceViolation::SecondConnect),
+            seq.from_client(&connect())
+        );
+        assert_eq!(
+            Err(SequenceViolation::SecondConnack),
+            seq.from_server(&connack)
+        );
+        assert_eq!(
+            Err(SequenceViolation::WrongDirection(PacketType::Suback)),
+            seq.from_client(
+                &Suback {
+                    pid,
+                    return_codes: Default::default()
+                }
+                .into()
+            )
+        );
+        seq.from_client(&Packet::Disconnect).unwrap();
+        assert_eq!(
+            Err(SequenceViolation::AfterDisconnect(PacketType::Pingreq)),
+            seq.from_client(&Packet::Pingreq)
+        );
+
+        let mut seq = SequenceChecker::new();
+        seq.from_client(&connect()).unwrap();
+        let qos0 = publish(QosPid::AtMostOnce, false);
+        assert_eq!(
+            Err(SequenceViolation::BeforeConnack(PacketType::Publish)),
+            seq.from_server(&qos0)
+        );
+        // Clients may send packets without waiting for the connack
+        seq.from_client(&qos0).unwrap();
+        seq.from_server(&connack).unwrap();
+        assert_eq!(
+            Err(SequenceViolation::AfterRefusal(PacketType::Publish)),
+            seq.from_server(&qos0)
+        );
+    }
+
+    #[test]
+    fn pids() {
+        let mut seq = connected();
+        let pid = Pid::new() + 6;
+        let qos2 = publish(QosPid::ExactlyOnce(pid), false);
+        seq.from_client(&qos2).unwrap();
+        seq.from_client(&publish(QosPid::ExactlyOnce(pid), true))
+            .unwrap();
+        assert_eq!(
+            Err(SequenceViolation::PidInUse(PacketType::Publish, pid)),
+            seq.from_client(&qos2)
+        );
+        assert_eq!(
+            Err(SequenceViolation::UnknownPid(PacketType::Pubrel, pid)),
+            seq.from_client(&Packet::Pubrel(pid))
+        );
+        assert_eq!(
+            Err(SequenceViolation::UnknownPid(PacketType::Puback, pid)),
+            seq.from_server(&Packet::Puback(pid))
+        );
+        seq.from_server(&Packet::Pubrec(pid)).unwrap();
+        seq.from_client(&Packet::Pubrel(pid)).unwrap();
+        seq.from_server(&Packet::Pubcomp(pid)).unwrap();
+        assert_eq!(
+            Err(SequenceViolation::UnknownPid(PacketType::Pubcomp, pid)),
+            seq.from_server(&Packet::Pubcomp(pid))
+        );
+
+        // Each side has its own pids
+        seq.from_client(&publish(QosPid::AtLeastOnce(pid), false))
+            .unwrap();
+        seq.from_server(&publish(QosPid::AtLeastOnce(pid), false))
+            .unwrap();
+        seq.from_server(&Packet::Puback(pid)).unwrap();
+        seq.from_client(&Packet::Puback(pid)).unwrap();
+
+        let unsubscribe = Unsubscribe {
+            pid,
+            topics: Default::default(),
+        };
+        seq.from_client(&unsubscribe.clone().into()).unwrap();
+        assert_eq!(
+            Err(SequenceViolation::PidInUse(PacketType::Unsubscribe, pid)),
+            seq.from_client(&unsubscribe.into())
+        );
+        assert_eq!(
+            "SUBACK for pid 7 which doesn't expect it",
+            seq.from_server(
+                &Suback {
+                    pid,
+                    return_codes: Default::default()
+                }
+                .into()
+            )
+            .unwrap_err()
+            .to_string()
+        );
+        seq.from_server(&Packet::Unsuback(pid)).unwrap();
+    }
+}
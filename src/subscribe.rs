@@ -24,11 +24,23 @@ pub struct SubscribeTopic {
     pub qos: QoS,
 }
 
+#[cfg(feature = "defmt")]
+impl defmt::Format for SubscribeTopic {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "SubscribeTopic {{ topic_path: {=str}, qos: {} }}",
+            self.topic_path.as_str(),
+            self.qos
+        )
+    }
+}
+
 impl SubscribeTopic {
-    pub(crate) fn from_buffer(buf: &[u8], offset: &mut usize) -> Result<Self, Error> {
-        let topic_path = LimitedString::from(read_str(buf, offset)?);
-        let qos = QoS::from_u8(buf[*offset])?;
-        *offset += 1;
+    pub(crate) fn from_buffer(buf: &Input, offset: &mut usize) -> Result<Self, Error> {
+        let topic_path =
+            LimitedString::from(read_str(buf, offset, PacketType::Subscribe, "topic")?);
+        let qos = QoS::from_u8(read_u8(buf, offset, PacketType::Subscribe, "qos")?)?;
         Ok(SubscribeTopic { topic_path, qos })
     }
 }
@@ -39,15 +51,16 @@ impl SubscribeTopic {
 ///
 /// [Suback]: struct.Subscribe.html
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum SubscribeReturnCodes {
     Success(QoS),
     Failure,
 }
 
 impl SubscribeReturnCodes {
-    pub(crate) fn from_buffer<'a>(buf: &'a [u8], offset: &mut usize) -> Result<Self, Error> {
-        let code = buf[*offset];
-        *offset += 1;
+    pub(crate) fn from_buffer(buf: &Input, offset: &mut usize) -> Result<Self, Error> {
+        let code = read_u8(buf, offset, PacketType::Suback, "return_code")?;
 
         if code == 0x80 {
             Ok(SubscribeReturnCodes::Failure)
@@ -68,6 +81,7 @@ impl SubscribeReturnCodes {
 ///
 /// [MQTT 3.8]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718063
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
 pub struct Subscribe {
     pub pid: Pid,
     pub topics: LimitedVec<SubscribeTopic>,
@@ -77,6 +91,7 @@ pub struct Subscribe {
 ///
 /// [MQTT 3.9]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718068
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
 pub struct Suback {
     pub pid: Pid,
     pub return_codes: LimitedVec<SubscribeReturnCodes>,
@@ -86,11 +101,50 @@ pub struct Suback {
 ///
 /// [MQTT 3.10]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718072
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
 pub struct Unsubscribe {
     pub pid: Pid,
     pub topics: LimitedVec<LimitedString>,
 }
 
+#[cfg(feature = "defmt")]
+impl defmt::Format for Subscribe {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "Subscribe {{ pid: {}, topics: {} }}",
+            self.pid,
+            &self.topics[..]
+        )
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Suback {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "Suback {{ pid: {}, return_codes: {} }}",
+            self.pid,
+            &self.return_codes[..]
+        )
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Unsubscribe {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "Unsubscribe {{ pid: {}, topics: [", self.pid);
+        for (i, topic) in self.topics.iter().enumerate() {
+            if i > 0 {
+                defmt::write!(f, ", ");
+            }
+            defmt::write!(f, "{=str}", topic.as_str());
+        }
+        defmt::write!(f, "] }}");
+    }
+}
+
 impl Subscribe {
     pub fn new(pid: Pid, topics: LimitedVec<SubscribeTopic>) -> Self {
         Subscribe { pid, topics }
@@ -98,33 +152,43 @@ impl Subscribe {
 
     pub(crate) fn from_buffer(
         remaining_len: usize,
-        buf: &[u8],
+        buf: &Input,
         offset: &mut usize,
     ) -> Result<Self, Error> {
         let payload_end = *offset + remaining_len;
-        let pid = Pid::from_buffer(buf, offset)?;
+        let pid = Pid::from_buffer(buf, offset, PacketType::Subscribe)?;
 
         let mut topics = LimitedVec::new();
         while *offset < payload_end {
+            let _start = *offset;
             let _res = topics.push(SubscribeTopic::from_buffer(buf, offset)?);
 
             #[cfg(not(feature = "std"))]
-            _res.map_err(|_| Error::InvalidLength)?;
+            _res.map_err(|_| Error::TooManyItems {
+                packet: PacketType::Subscribe,
+                offset: _start,
+                field: "topic",
+            })?;
         }
 
         Ok(Subscribe { pid, topics })
     }
 
+    /// Length: pid(2) + topic.for_each(2+len + qos(1))
+    pub(crate) fn remaining_len(&self) -> Result<usize, Error> {
+        let mut length = 2;
+        for topic in &self.topics {
+            length += string_len(&topic.topic_path)? + 1;
+        }
+        Ok(length)
+    }
+
     pub(crate) fn to_buffer(&self, buf: &mut [u8], offset: &mut usize) -> Result<usize, Error> {
         let header: u8 = 0b10000010;
+        let length = self.remaining_len()?;
         check_remaining(buf, offset, 1)?;
         write_u8(buf, offset, header)?;
 
-        // Length: pid(2) + topic.for_each(2+len + qos(1))
-        let mut length = 2;
-        for topic in &self.topics {
-            length += topic.topic_path.len() + 2 + 1;
-        }
         let write_len = write_length(buf, offset, length)? + 1;
 
         // Pid
@@ -147,29 +211,41 @@ impl Unsubscribe {
 
     pub(crate) fn from_buffer(
         remaining_len: usize,
-        buf: &[u8],
+        buf: &Input,
         offset: &mut usize,
     ) -> Result<Self, Error> {
         let payload_end = *offset + remaining_len;
-        let pid = Pid::from_buffer(buf, offset)?;
+        let pid = Pid::from_buffer(buf, offset, PacketType::Unsubscribe)?;
 
         let mut topics = LimitedVec::new();
         while *offset < payload_end {
-            let _res = topics.push(LimitedString::from(read_str(buf, offset)?));
+            let _start = *offset;
+            let topic = read_str(buf, offset, PacketType::Unsubscribe, "topic")?;
+            let _res = topics.push(LimitedString::from(topic));
 
             #[cfg(not(feature = "std"))]
-            _res.map_err(|_| Error::InvalidLength)?;
+            _res.map_err(|_| Error::TooManyItems {
+                packet: PacketType::Unsubscribe,
+                offset: _start,
+                field: "topic",
+            })?;
         }
 
         Ok(Unsubscribe { pid, topics })
     }
 
-    pub(crate) fn to_buffer(&self, buf: &mut [u8], offset: &mut usize) -> Result<usize, Error> {
-        let header: u8 = 0b10100010;
+    /// Length: pid(2) + topic.for_each(2+len)
+    pub(crate) fn remaining_len(&self) -> Result<usize, Error> {
         let mut length = 2;
         for topic in &self.topics {
-            length += 2 + topic.len();
+            length += string_len(topic)?;
         }
+        Ok(length)
+    }
+
+    pub(crate) fn to_buffer(&self, buf: &mut [u8], offset: &mut usize) -> Result<usize, Error> {
+        let header: u8 = 0b10100010;
+        let length = self.remaining_len()?;
         check_remaining(buf, offset, 1)?;
         write_u8(buf, offset, header)?;
 
@@ -189,26 +265,36 @@ impl Suback {
 
     pub(crate) fn from_buffer(
         remaining_len: usize,
-        buf: &[u8],
+        buf: &Input,
         offset: &mut usize,
     ) -> Result<Self, Error> {
         let payload_end = *offset + remaining_len;
-        let pid = Pid::from_buffer(buf, offset)?;
+        let pid = Pid::from_buffer(buf, offset, PacketType::Suback)?;
 
         let mut return_codes = LimitedVec::new();
         while *offset < payload_end {
+            let _start = *offset;
             let _res = return_codes.push(SubscribeReturnCodes::from_buffer(buf, offset)?);
 
             #[cfg(not(feature = "std"))]
-            _res.map_err(|_| Error::InvalidLength)?;
+            _res.map_err(|_| Error::TooManyItems {
+                packet: PacketType::Suback,
+                offset: _start,
+                field: "return_code",
+            })?;
         }
 
         Ok(Suback { pid, return_codes })
     }
 
+    /// Length: pid(2) + return_codes.len()
+    pub(crate) fn remaining_len(&self) -> usize {
+        2 + self.return_codes.len()
+    }
+
     pub(crate) fn to_buffer(&self, buf: &mut [u8], offset: &mut usize) -> Result<usize, Error> {
         let header: u8 = 0b10010000;
-        let length = 2 + self.return_codes.len();
+        let length = self.remaining_len();
         check_remaining(buf, offset, 1)?;
         write_u8(buf, offset, header)?;
 
@@ -1,5 +1,9 @@
 use core::{convert::TryFrom, fmt, num::NonZeroU16};
-use crate::encoder::write_u16;
+use crate::{
+    decoder::{read_u16, Input},
+    encoder::write_u16,
+    ConnectReturnCode, PacketType, Violation,
+};
 
 #[cfg(feature = "derive")]
 use serde::{Deserialize, Serialize};
@@ -17,36 +21,127 @@ use std::{
 /// [`decode()`]: fn.decode.html
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Error {
-    /// Not enough space in the write buffer.
-    ///
-    /// It is the caller's responsiblity to pass a big enough buffer to `encode()`.
+    /// The writer didn't accept all the bytes, like `std::io::ErrorKind::WriteZero`.
     WriteZero,
+    /// Not enough space in the buffer passed to [`encode_slice()`], which needs `required` bytes.
+    ///
+    /// Nothing was written, so the packet can be encoded again into a big enough buffer.
+    ///
+    /// [`encode_slice()`]: fn.encode_slice.html
+    BufferTooSmall { required: usize, available: usize },
     /// Tried to encode or decode a ProcessIdentifier==0.
     InvalidPid,
     /// Tried to decode a QoS > 2.
     InvalidQos(u8),
     /// Tried to decode a ConnectReturnCode > 5.
     InvalidConnectReturnCode(u8),
-    /// Tried to decode an unknown protocol.
+    /// Tried to decode a connect packet with an unknown protocol name or level, like MQTT 5.
+    ///
+    /// Decoding stops right after the protocol level. A server should answer with the connack
+    /// given by [`connect_return_code()`], or just disconnect if there is none. Without `std`, the
+    /// name is truncated to 10 bytes.
+    ///
+    /// [`connect_return_code()`]: #method.connect_return_code
     #[cfg(feature = "std")]
     InvalidProtocol(std::string::String, u8),
     #[cfg(not(feature = "std"))]
     InvalidProtocol(heapless::String<heapless::consts::U10>, u8),
-    /// Tried to decode an invalid fixed header (packet type, flags, or remaining_length).
-    InvalidHeader,
-    /// Trying to encode/decode an invalid length.
+    /// Tried to decode a fixed header with packet type 0 or 15.
+    InvalidPacketType(u8),
+    /// Tried to decode a fixed header whose flags are invalid for its packet type.
+    InvalidFlags(PacketType, u8),
+    /// Tried to decode a remaining length encoded with more than 4 bytes.
+    InvalidRemainingLength,
+    /// Tried to decode a field that extends past the end of the packet.
+    ///
+    /// `offset` is where the field starts within the packet, `len` is its (announced) length, and
+    /// `remaining` is the number of bytes left in the packet at that point.
+    FieldTooLong {
+        packet: PacketType,
+        offset: usize,
+        field: &'static str,
+        len: usize,
+        remaining: usize,
+    },
+    /// Tried to decode more list entries than fit in a `heapless::Vec`.
+    TooManyItems {
+        packet: PacketType,
+        offset: usize,
+        field: &'static str,
+    },
+    /// Tried to decode a packet bigger than the maximum packet size.
+    ///
+    /// `size` is the announced packet size, including the fixed header. When reading into a fixed
+    /// buffer, `max` is the buffer size.
+    PacketTooLarge { size: usize, max: usize },
+    /// Decoded a packet type that is not allowed in this direction, with [`decode_from_client()`]
+    /// or [`decode_from_server()`].
+    ///
+    /// [`decode_from_client()`]: fn.decode_from_client.html
+    /// [`decode_from_server()`]: fn.decode_from_server.html
+    WrongDirection(PacketType),
+    /// Tried to patch the dup flag or pid of an encoded packet that isn't a QoS 1 or 2 publish.
+    NotQosPublish(PacketType),
+    /// Tried to encode a packet longer than the maximum remaining length.
     ///
     /// The difference with `WriteZero`/`UnexpectedEof` is that it refers to an invalid/corrupt
     /// length rather than a buffer size issue.
     InvalidLength,
+    /// Tried to encode a string or binary field longer than 65535 bytes.
+    StringTooLong(usize),
+    /// Tried to encode or decode a string containing U+0000, a control character, or a
+    /// non-character ([MQTT-1.5.3]).
+    ///
+    /// [MQTT-1.5.3]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718016
+    InvalidChar(char),
+    /// Tried to encode a packet that breaks a spec rule, with [`encode_slice_validated()`].
+    ///
+    /// [`encode_slice_validated()`]: fn.encode_slice_validated.html
+    Violation(Violation),
     /// Trying to decode a non-utf8 string.
     InvalidString(core::str::Utf8Error),
+    /// The transport reached end of stream in the middle of a packet.
+    UnexpectedEof,
     /// Catch-all error when converting from `std::io::Error`.
     ///
     /// Note: Only available when std is available.
     /// You'll hopefully never see this.
     #[cfg(feature = "std")]
     IoError(ErrorKind, std::string::String),
+    /// Catch-all error from an `embedded_io` transport.
+    ///
+    /// Note: Only available with the `embedded-io` feature.
+    #[cfg(feature = "embedded-io")]
+    EmbeddedIo(embedded_io::ErrorKind),
+}
+
+impl Error {
+    /// The connack return code that a server should answer with, when decoding a connect packet
+    /// failed with this error.
+    ///
+    /// Only an unsupported protocol level of MQTT requires a `RefusedProtocolVersion` connack
+    /// (MQTT-3.1.2-2). For an unknown protocol name, this returns `None`: the server may disconnect
+    /// without a connack (see "Protocol Name" in [MQTT 3.1]).
+    ///
+    /// ```
+    /// # use mqttrs::*;
+    /// // MQTT 5 connect, with client_id "a" and no properties.
+    /// let buf = [0b00010000, 14, 0, 4, b'M', b'Q', b'T', b'T', 5, 0b10, 0, 30, 0, 0, 1, b'a'];
+    /// let err = decode_slice(&buf).unwrap_err();
+    /// assert_eq!(Error::InvalidProtocol("MQTT".into(), 5), err);
+    /// assert_eq!(Some(ConnectReturnCode::RefusedProtocolVersion), err.connect_return_code());
+    /// ```
+    ///
+    /// [MQTT 3.1]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718028
+    pub fn connect_return_code(&self) -> Option<ConnectReturnCode> {
+        match self {
+            Error::InvalidProtocol(name, _) => match name.as_str() {
+                "MQTT" | "MQIsdp" => Some(ConnectReturnCode::RefusedProtocolVersion),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
 }
 
 #[cfg(feature = "std")]
@@ -54,7 +149,178 @@ impl ErrorTrait for Error {}
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            Error::WriteZero => write!(f, "the writer did not accept all the bytes"),
+            Error::BufferTooSmall {
+                required,
+                available,
+            } => write!(
+                f,
+                "buffer too small: {} bytes required, {} available",
+                required, available
+            ),
+            Error::InvalidPid => write!(f, "invalid packet identifier 0"),
+            Error::InvalidQos(q) => write!(f, "invalid QoS {}, must be 0, 1 or 2", q),
+            Error::InvalidConnectReturnCode(c) => {
+                write!(f, "invalid connect return code {}, must be 0 to 5", c)
+            }
+            Error::InvalidProtocol(name, level) => {
+                write!(
+                    f,
+                    "unsupported protocol {:?} level {}",
+                    name.as_str(),
+                    level
+                )
+            }
+            Error::InvalidPacketType(t) => write!(f, "invalid packet type {}", t),
+            Error::InvalidFlags(packet, flags) => {
+                write!(
+                    f,
+                    "invalid fixed header flags {:#06b} for {}",
+                    flags, packet
+                )
+            }
+            Error::InvalidRemainingLength => {
+                write!(
+                    f,
+                    "invalid remaining length, encoded with more than 4 bytes"
+                )
+            }
+            Error::FieldTooLong {
+                packet,
+                offset,
+                field,
+                len,
+                remaining,
+            } => write!(
+                f,
+                "{} at offset {}: {} length {} exceeds remaining {}",
+                packet, offset, field, len, remaining
+            ),
+            Error::TooManyItems {
+                packet,
+                offset,
+                field,
+            } => write!(
+                f,
+                "{} at offset {}: too many {} entries",
+                packet, offset, field
+            ),
+            Error::PacketTooLarge { size, max } => {
+                write!(f, "packet size {} exceeds maximum {}", size, max)
+            }
+            Error::WrongDirection(typ) if typ.sent_by_client() => {
+                write!(f, "{} can only be sent by a client", typ)
+            }
+            Error::WrongDirection(typ) => write!(f, "{} can only be sent by a server", typ),
+            Error::NotQosPublish(PacketType::Publish) => write!(f, "PUBLISH at QoS 0 has no pid"),
+            Error::NotQosPublish(typ) => write!(f, "{} is not a PUBLISH", typ),
+            Error::InvalidLength => write!(
+                f,
+                "invalid length: packet longer than the maximum remaining length"
+            ),
+            Error::StringTooLong(len) => {
+                write!(f, "string length {} exceeds maximum 65535", len)
+            }
+            Error::InvalidChar(c) => write!(f, "string contains disallowed character {:?}", c),
+            Error::Violation(v) => write!(f, "spec violation {}", v),
+            Error::InvalidString(e) => write!(f, "invalid UTF-8 string: {}", e),
+            Error::UnexpectedEof => write!(f, "unexpected end of stream in the middle of a packet"),
+            #[cfg(feature = "std")]
+            Error::IoError(_, msg) => write!(f, "I/O error: {}", msg),
+            #[cfg(feature = "embedded-io")]
+            Error::EmbeddedIo(k) => write!(f, "I/O error: {:?}", k),
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Error {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Error::WriteZero => defmt::write!(f, "WriteZero"),
+            Error::BufferTooSmall {
+                required,
+                available,
+            } => defmt::write!(
+                f,
+                "BufferTooSmall(required: {}, available: {})",
+                required,
+                available
+            ),
+            Error::InvalidPid => defmt::write!(f, "InvalidPid"),
+            Error::InvalidQos(q) => defmt::write!(f, "InvalidQos({})", q),
+            Error::InvalidConnectReturnCode(c) => {
+                defmt::write!(f, "InvalidConnectReturnCode({})", c)
+            }
+            Error::InvalidProtocol(name, level) => {
+                defmt::write!(f, "InvalidProtocol({=str}, {})", name.as_str(), level)
+            }
+            Error::InvalidPacketType(t) => defmt::write!(f, "InvalidPacketType({})", t),
+            Error::InvalidFlags(packet, flags) => {
+                defmt::write!(f, "InvalidFlags({}, {=u8:#06b})", packet, flags)
+            }
+            Error::InvalidRemainingLength => defmt::write!(f, "InvalidRemainingLength"),
+            Error::FieldTooLong {
+                packet,
+                offset,
+                field,
+                len,
+                remaining,
+            } => defmt::write!(
+                f,
+                "FieldTooLong({} at offset {}: {=str} length {} exceeds remaining {})",
+                packet,
+                offset,
+                field,
+                len,
+                remaining
+            ),
+            Error::TooManyItems {
+                packet,
+                offset,
+                field,
+            } => defmt::write!(
+                f,
+                "TooManyItems({} at offset {}: {=str})",
+                packet,
+                offset,
+                field
+            ),
+            Error::PacketTooLarge { size, max } => {
+                defmt::write!(f, "PacketTooLarge(size: {}, max: {})", size, max)
+            }
+            Error::WrongDirection(typ) => defmt::write!(f, "WrongDirection({})", typ),
+            Error::NotQosPublish(typ) => defmt::write!(f, "NotQosPublish({})", typ),
+            Error::InvalidLength => defmt::write!(f, "InvalidLength"),
+            Error::StringTooLong(len) => defmt::write!(f, "StringTooLong({})", len),
+            Error::InvalidChar(c) => defmt::write!(f, "InvalidChar({=u32:#x})", *c as u32),
+            Error::Violation(v) => defmt::write!(f, "Violation({})", v),
+            Error::InvalidString(e) => {
+                defmt::write!(f, "InvalidString(valid_up_to: {})", e.valid_up_to())
+            }
+            Error::UnexpectedEof => defmt::write!(f, "UnexpectedEof"),
+            #[cfg(feature = "std")]
+            Error::IoError(_, msg) => defmt::write!(f, "IoError({=str})", msg.as_str()),
+            #[cfg(feature = "embedded-io")]
+            Error::EmbeddedIo(k) => defmt::write!(f, "EmbeddedIo({})", k),
+        }
+    }
+}
+
+/// Formats a byte slice as its length and a hex prefix, to keep defmt logs short.
+#[cfg(feature = "defmt")]
+pub(crate) struct BytesPrefix<'a>(pub &'a [u8]);
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for BytesPrefix<'_> {
+    fn format(&self, f: defmt::Formatter) {
+        const PREFIX: usize = 8;
+        if self.0.len() > PREFIX {
+            defmt::write!(f, "{}B {=[u8]:02x}..", self.0.len(), &self.0[..PREFIX])
+        } else {
+            defmt::write!(f, "{}B {=[u8]:02x}", self.0.len(), self.0)
+        }
     }
 }
 
@@ -62,7 +328,9 @@ impl fmt::Display for Error {
 impl From<Error> for IoError {
     fn from(err: Error) -> IoError {
         match err {
-            Error::WriteZero => IoError::new(ErrorKind::WriteZero, err),
+            Error::WriteZero | Error::BufferTooSmall { .. } => {
+                IoError::new(ErrorKind::WriteZero, err)
+            }
             _ => IoError::new(ErrorKind::InvalidData, err),
         }
     }
@@ -78,6 +346,26 @@ impl From<IoError> for Error {
     }
 }
 
+/// Truncate `s` to at most `max` bytes, on a char boundary.
+pub(crate) fn truncate_str(s: &str, max: usize) -> &str {
+    let mut end = s.len().min(max);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+/// Check the MQTT string rules that come on top of UTF-8 validity: no U+0000 (MQTT-1.5.3-2), and
+/// no control characters or non-characters, which the spec recommends rejecting.
+pub(crate) fn check_str(s: &str) -> Result<(), Error> {
+    match s.chars().find(|&c| {
+        c.is_control() || matches!(c as u32, 0xFDD0..=0xFDEF) || c as u32 & 0xFFFE == 0xFFFE
+    }) {
+        Some(c) => Err(Error::InvalidChar(c)),
+        None => Ok(()),
+    }
+}
+
 /// Packet Identifier.
 ///
 /// For packets with [`QoS::AtLeastOne` or `QoS::ExactlyOnce`] delivery.
@@ -120,10 +408,12 @@ impl Pid {
         self.0.get()
     }
 
-    pub(crate) fn from_buffer<'a>(buf: &'a [u8], offset: &mut usize) -> Result<Self, Error> {
-        let pid = ((buf[*offset] as u16) << 8) | buf[*offset + 1] as u16;
-        *offset += 2;
-        Self::try_from(pid)
+    pub(crate) fn from_buffer(
+        buf: &Input,
+        offset: &mut usize,
+        packet: PacketType,
+    ) -> Result<Self, Error> {
+        Self::try_from(read_u16(buf, offset, packet, "pid")?)
     }
 
     pub(crate) fn to_buffer(self, buf: &mut [u8], offset: &mut usize) -> Result<(), Error> {
@@ -131,6 +421,19 @@ impl Pid {
     }
 }
 
+#[cfg(feature = "defmt")]
+impl defmt::Format for Pid {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "{}", self.get())
+    }
+}
+
+impl fmt::Display for Pid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.get())
+    }
+}
+
 impl Default for Pid {
     fn default() -> Pid {
         Pid::new()
@@ -188,6 +491,7 @@ impl TryFrom<u16> for Pid {
 /// [Quality of Service]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718099
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum QoS {
     /// `QoS 0`. No ack needed.
     AtMostOnce,
@@ -216,6 +520,13 @@ impl QoS {
     }
 }
 
+impl fmt::Display for QoS {
+    /// Formats as `q0`, `q1` or `q2`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "q{}", self.to_u8())
+    }
+}
+
 /// Combined [`QoS`]/[`Pid`].
 ///
 /// Used only in [`Publish`] packets.
@@ -225,6 +536,7 @@ impl QoS {
 /// [`Pid`]: struct.Pid.html
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum QosPid {
     AtMostOnce,
     AtLeastOnce(Pid),
@@ -267,10 +579,22 @@ impl QosPid {
 
 #[cfg(test)]
 mod test {
+    use super::truncate_str;
     use crate::Pid;
     use core::convert::TryFrom;
     use std::vec;
 
+    #[test]
+    fn truncate() {
+        assert_eq!("MQTT", truncate_str("MQTT", 10));
+        assert_eq!("MQTT-very-", truncate_str("MQTT-very-long", 10));
+        // 'é' is 2 bytes long, the 10th byte is in the middle of one
+        assert_eq!("MQTT-éé", truncate_str("MQTT-éééé", 10));
+        // What Protocol::new() does without std, which used to panic
+        let name = heapless::String::<heapless::consts::U10>::from(truncate_str("MQTT-éééé", 10));
+        assert_eq!("MQTT-éé", name.as_str());
+    }
+
     #[test]
     fn pid_add_sub() {
         let t: Vec<(u16, u16, u16, u16)> = vec![
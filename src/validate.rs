@@ -0,0 +1,347 @@
+use crate::{subscribe::LimitedVec, utils::check_str, *};
+use core::fmt;
+
+/// A spec rule broken by a packet, found by [Packet::validate()].
+///
+/// The rules are those of [MQTT 3.1.1] that `mqttrs` can't enforce through its types. Use
+/// [`rule()`] to get the spec's conformance statement number.
+///
+/// [Packet::validate()]: enum.Packet.html#method.validate
+/// [`rule()`]: #method.rule
+/// [MQTT 3.1.1]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Violation {
+    /// String or binary field longer than 65535 bytes.
+    TooLong { field: &'static str, len: usize },
+    /// String containing U+0000, a control character, or a non-character.
+    InvalidChar { field: &'static str, c: char },
+    /// Empty topic name or topic filter.
+    EmptyTopic { field: &'static str },
+    /// Publish topic name containing `+` or `#`.
+    WildcardInTopicName,
+    /// Last will topic containing `+` or `#`.
+    WildcardInWillTopic,
+    /// Topic filter with a `#` that isn't alone in the last level.
+    InvalidMultiLevelWildcard { field: &'static str },
+    /// Topic filter with a `+` that isn't alone in its level.
+    InvalidSingleLevelWildcard { field: &'static str },
+    /// Subscribe or unsubscribe without any topic.
+    NoTopics(PacketType),
+    /// Suback without any return code.
+    NoReturnCodes,
+    /// Connect with a password but no username.
+    PasswordWithoutUsername,
+    /// Connect with an empty client id and without a clean session.
+    EmptyClientIdWithoutCleanSession,
+    /// Publish with the dup flag set at QoS 0.
+    DupAtQos0,
+}
+
+impl Violation {
+    /// The conformance statement number of the broken rule, like `"MQTT-3.3.2-2"`.
+    ///
+    /// Length, control character and non-character limits aren't numbered statements, they refer
+    /// to their spec section instead.
+    pub fn rule(&self) -> &'static str {
+        match self {
+            Violation::TooLong { .. } => "MQTT-1.5.3",
+            Violation::InvalidChar { c: '\0', .. } => "MQTT-1.5.3-2",
+            Violation::InvalidChar { .. } => "MQTT-1.5.3",
+            Violation::EmptyTopic { .. } => "MQTT-4.7.3-1",
+            Violation::WildcardInTopicName => "MQTT-3.3.2-2",
+            Violation::WildcardInWillTopic => "MQTT-4.7.1-1",
+            Violation::InvalidMultiLevelWildcard { .. } => "MQTT-4.7.1-2",
+            Violation::InvalidSingleLevelWildcard { .. } => "MQTT-4.7.1-3",
+            Violation::NoTopics(PacketType::Unsubscribe) => "MQTT-3.10.3-2",
+            Violation::NoTopics(_) => "MQTT-3.8.3-3",
+            Violation::NoReturnCodes => "MQTT-3.8.4-5",
+            Violation::PasswordWithoutUsername => "MQTT-3.1.2-22",
+            Violation::EmptyClientIdWithoutCleanSession => "MQTT-3.1.3-7",
+            Violation::DupAtQos0 => "MQTT-3.3.1-2",
+        }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: ", self.rule())?;
+        match self {
+            Violation::TooLong { field, len } => {
+                write!(f, "{} length {} exceeds maximum 65535", field, len)
+            }
+            Violation::InvalidChar { field, c } => {
+                write!(f, "{} contains disallowed character {:?}", field, c)
+            }
+            Violation::EmptyTopic { field } => write!(f, "{} is empty", field),
+            Violation::WildcardInTopicName => write!(f, "topic contains a wildcard"),
+            Violation::WildcardInWillTopic => write!(f, "will_topic contains a wildcard"),
+            Violation::InvalidMultiLevelWildcard { field } => {
+                write!(f, "{} has a '#' that isn't alone in the last level", field)
+            }
+            Violation::InvalidSingleLevelWildcard { field } => {
+                write!(f, "{} has a '+' that isn't alone in its level", field)
+            }
+            Violation::NoTopics(packet) => write!(f, "{} without topics", packet),
+            Violation::NoReturnCodes => write!(f, "SUBACK without return codes"),
+            Violation::PasswordWithoutUsername => write!(f, "password without username"),
+            Violation::EmptyClientIdWithoutCleanSession => {
+                write!(f, "empty client_id without clean_session")
+            }
+            Violation::DupAtQos0 => write!(f, "dup flag set at QoS 0"),
+        }
+    }
+}
+
+impl Packet<'_> {
+    /// Check the packet against the spec rules that encoding doesn't enforce.
+    ///
+    /// Returns every [Violation] found. Without the `std` feature, only the first 5 are kept.
+    ///
+    /// ```
+    /// # use mqttrs::*;
+    /// let publish = Publish {
+    ///     dup: true,
+    ///     qospid: QosPid::AtMostOnce,
+    ///     retain: false,
+    ///     topic_name: "sensors/#",
+    ///     payload: b"",
+    /// };
+    /// let violations = Packet::from(publish).validate().unwrap_err();
+    /// let rules: Vec<_> = violations.iter().map(|v| v.rule()).collect();
+    /// assert_eq!(rules, ["MQTT-3.3.1-2", "MQTT-3.3.2-2"]);
+    /// ```
+    ///
+    /// [Violation]: enum.Violation.html
+    pub fn validate(&self) -> Result<(), LimitedVec<Violation>> {
+        let mut v = LimitedVec::new();
+        match self {
+            Packet::Connect(connect) => {
+                if connect.client_id.is_empty() && !connect.clean_session {
+                    report(&mut v, Violation::EmptyClientIdWithoutCleanSession);
+                }
+                check_string(&mut v, "client_id", connect.client_id);
+                if let Some(will) = &connect.last_will {
+                    check_topic_name(
+                        &mut v,
+                        "will_topic",
+                        will.topic,
+                        Violation::WildcardInWillTopic,
+                    );
+                    check_len(&mut v, "will_message", will.message.len());
+                }
+                if let Some(username) = connect.username {
+                    check_string(&mut v, "username", username);
+                }
+                if let Some(password) = connect.password {
+                    if connect.username.is_none() {
+                        report(&mut v, Violation::PasswordWithoutUsername);
+                    }
+                    check_len(&mut v, "password", password.len());
+                }
+            }
+            Packet::Publish(publish) => {
+                if publish.dup && publish.qospid == QosPid::AtMostOnce {
+                    report(&mut v, Violation::DupAtQos0);
+                }
+                check_topic_name(
+                    &mut v,
+                    "topic",
+                    publish.topic_name,
+                    Violation::WildcardInTopicName,
+                );
+            }
+            Packet::Subscribe(subscribe) => {
+                if subscribe.topics.is_empty() {
+                    report(&mut v, Violation::NoTopics(PacketType::Subscribe));
+                }
+                for topic in &subscribe.topics {
+                    check_topic_filter(&mut v, &topic.topic_path);
+                }
+            }
+            Packet::Suback(suback) if suback.return_codes.is_empty() => {
+                report(&mut v, Violation::NoReturnCodes);
+            }
+            Packet::Unsubscribe(unsubscribe) => {
+                if unsubscribe.topics.is_empty() {
+                    report(&mut v, Violation::NoTopics(PacketType::Unsubscribe));
+                }
+                for topic in &unsubscribe.topics {
+                    check_topic_filter(&mut v, topic);
+                }
+            }
+            _ => (),
+        }
+        if v.is_empty() {
+            Ok(())
+        } else {
+            Err(v)
+        }
+    }
+}
+
+fn report(violations: &mut LimitedVec<Violation>, violation: Violation) {
+    #[cfg(feature = "std")]
+    violations.push(violation);
+    // Without std, violations past the capacity are dropped
+    #[cfg(not(feature = "std"))]
+    let _ = violations.push(violation);
+}
+
+fn check_len(v: &mut LimitedVec<Violation>, field: &'static str, len: usize) {
+    if len > u16::MAX as usize {
+        report(v, Violation::TooLong { field, len });
+    }
+}
+
+fn check_string(v: &mut LimitedVec<Violation>, field: &'static str, s: &str) {
+    check_len(v, field, s.len());
+    if let Err(Error::InvalidChar(c)) = check_str(s) {
+        report(v, Violation::InvalidChar { field, c });
+    }
+}
+
+/// Check a topic name, reporting `wildcard` if it contains one.
+fn check_topic_name(
+    v: &mut LimitedVec<Violation>,
+    field: &'static str,
+    topic: &str,
+    wildcard: Violation,
+) {
+    if topic.is_empty() {
+        report(v, Violation::EmptyTopic { field });
+    }
+    if topic.contains(&['+', '#'][..]) {
+        report(v, wildcard);
+    }
+    check_string(v, field, topic);
+}
+
+fn check_topic_filter(v: &mut LimitedVec<Violation>, filter: &str) {
+    let field = "topics";
+    if filter.is_empty() {
+        report(v, Violation::EmptyTopic { field });
+    }
+    let mut levels = filter.split('/').peekable();
+    while let Some(level) = levels.next() {
+        if level.contains('#') && (level != "#" || levels.peek().is_some()) {
+            report(v, Violation::InvalidMultiLevelWildcard { field });
+        }
+        if level.contains('+') && level != "+" {
+            report(v, Violation::InvalidSingleLevelWildcard { field });
+        }
+    }
+    check_string(v, field, filter);
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+    use subscribe::{LimitedString, LimitedVec};
+
+    fn rules(packet: Packet) -> Vec<&'static str> {
+        match packet.validate() {
+            Ok(()) => vec![],
+            Err(v) => v.iter().map(|v| v.rule()).collect(),
+        }
+    }
+
+    fn subscribe(filters: &[&str]) -> Packet<'static> {
+        let topics: LimitedVec<_> = filters
+            .iter()
+            .map(|&f| SubscribeTopic {
+                topic_path: LimitedString::from(f),
+                qos: QoS::AtMostOnce,
+            })
+            .collect();
+        Subscribe::new(Pid::new(), topics).into()
+    }
+
+    #[test]
+    fn topic_filters() {
+        for &valid in &[
+            "a", "a/b", "#", "a/#", "+", "+/+", "a/+/c", "/", "a//b", "$SYS/#",
+        ] {
+            assert_eq!(rules(subscribe(&[valid])), Vec::<&str>::new(), "{}", valid);
+        }
+        for &(invalid, rule) in &[
+            ("", "MQTT-4.7.3-1"),
+            ("a#", "MQTT-4.7.1-2"),
+            ("#/a", "MQTT-4.7.1-2"),
+            ("a/b#", "MQTT-4.7.1-2"),
+            ("a+", "MQTT-4.7.1-3"),
+            ("a/+b/c", "MQTT-4.7.1-3"),
+            ("a\0", "MQTT-1.5.3-2"),
+        ] {
+            assert_eq!(rules(subscribe(&[invalid])), [rule], "{}", invalid);
+        }
+        assert_eq!(rules(subscribe(&[])), ["MQTT-3.8.3-3"]);
+        let unsubscribe = Unsubscribe::new(Pid::new(), LimitedVec::new());
+        assert_eq!(rules(unsubscribe.into()), ["MQTT-3.10.3-2"]);
+        let suback = Suback::new(Pid::new(), LimitedVec::new());
+        assert_eq!(rules(suback.into()), ["MQTT-3.8.4-5"]);
+    }
+
+    #[test]
+    fn connect() {
+        let long = "u".repeat(70_000);
+        let connect = Connect {
+            protocol: Protocol::MQTT311,
+            keep_alive: 10,
+            client_id: "",
+            clean_session: false,
+            last_will: Some(LastWill {
+                topic: "will/#",
+                message: b"bye",
+                qos: QoS::AtMostOnce,
+                retain: false,
+            }),
+            username: None,
+            password: Some(long.as_bytes()),
+        };
+        let violations = Packet::from(connect.clone()).validate().unwrap_err();
+        assert_eq!(
+            violations,
+            [
+                Violation::EmptyClientIdWithoutCleanSession,
+                Violation::WildcardInWillTopic,
+                Violation::PasswordWithoutUsername,
+                Violation::TooLong {
+                    field: "password",
+                    len: 70_000
+                },
+            ]
+        );
+        assert_eq!(
+            format!("{}", violations[1]),
+            "MQTT-4.7.1-1: will_topic contains a wildcard"
+        );
+
+        let valid = Connect {
+            client_id: "c",
+            last_will: None,
+            username: Some("u"),
+            password: Some(b"p"),
+            ..connect
+        };
+        assert_eq!(Ok(()), Packet::from(valid).validate());
+    }
+
+    #[test]
+    fn encode_validated() {
+        let mut buf = [0u8; 64];
+        let publish = Publish {
+            dup: false,
+            qospid: QosPid::AtMostOnce,
+            retain: false,
+            topic_name: "a/+",
+            payload: b"",
+        };
+        assert_eq!(Ok(7), encode_slice(&publish.clone().into(), &mut buf));
+        assert_eq!(
+            Err(Error::Violation(Violation::WildcardInTopicName)),
+            encode_slice_validated(&publish.into(), &mut buf)
+        );
+        assert_eq!(Ok(2), encode_slice_validated(&Packet::Pingreq, &mut buf));
+    }
+}